@@ -0,0 +1,102 @@
+//! Measures how request throughput scales with the server's `--threads` and
+//! `--shards`.
+//!
+//! Build the server first, then run the benchmark from this directory:
+//!
+//!     (cd ../cache-server && cargo build --release)
+//!     cargo bench
+//!
+//! The server binary is looked up at `CACHE_SERVER_BIN`, defaulting to the
+//! release build in the neighbouring crate. For each shard and thread count a
+//! fresh server is started on a free local port and hammered by a fixed number of
+//! clients, each on its own connection, doing an even mix of puts and gets.
+
+extern crate cache_client;
+
+use std::env;
+use std::net::{ TcpListener, TcpStream };
+use std::process::{ self, Command, Child };
+use std::thread;
+use std::time::{ Duration, Instant };
+use cache_client::{ CacheClientBuilder, Entry };
+
+const CLIENTS: usize = 32;
+const REQUESTS_PER_CLIENT: usize = 5000;
+const SERVER_THREADS: [usize; 4] = [1, 2, 4, 8];
+const SERVER_SHARDS: [usize; 2] = [1, 16];
+
+fn main() {
+    let binary = env::var("CACHE_SERVER_BIN")
+                     .unwrap_or("../cache-server/target/release/cache-server".to_string());
+
+    println!("{:>8} {:>8} {:>12} {:>10}", "shards", "threads", "requests/s", "speedup");
+    let mut baseline = None;
+    for &shards in SERVER_SHARDS.iter() {
+        for &threads in SERVER_THREADS.iter() {
+            let rate = run(&binary, shards, threads);
+            let speedup = rate / *baseline.get_or_insert(rate);
+            println!("{:>8} {:>8} {:>12.0} {:>9.2}x", shards, threads, rate, speedup);
+        }
+    }
+}
+
+fn run(binary: &str, shards: usize, threads: usize) -> f64 {
+    let port = free_port();
+    let mut server = start_server(binary, port, shards, threads);
+    let address = format!("127.0.0.1:{}", port);
+
+    let started = Instant::now();
+    let clients: Vec<_> = (0..CLIENTS).map(|client| {
+        let address = address.clone();
+        thread::spawn(move || {
+            let cache = CacheClientBuilder::new(address).pool_size(1).build().unwrap();
+            for request in 0..REQUESTS_PER_CLIENT {
+                let key = format!("key-{}-{}", client, request % 100).into_bytes();
+                if request % 2 == 0 {
+                    cache.put(Entry { key: key, value: vec![0; 64] }).unwrap();
+                } else {
+                    cache.get(Entry { key: key, value: vec![] }).unwrap();
+                }
+            }
+        })
+    }).collect();
+    for client in clients {
+        client.join().unwrap();
+    }
+    let elapsed = started.elapsed();
+
+    let _ = server.kill();
+    let _ = server.wait();
+    let seconds = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+    (CLIENTS * REQUESTS_PER_CLIENT) as f64 / seconds
+}
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0").and_then(|listener| listener.local_addr()).unwrap().port()
+}
+
+fn start_server(binary: &str, port: u16, shards: usize, threads: usize) -> Child {
+    let server = Command::new(binary)
+                     .args(&["--address", "127.0.0.1",
+                             "--port", &port.to_string(),
+                             "--shards", &shards.to_string(),
+                             "--threads", &threads.to_string(),
+                             "--capacity", "100000"])
+                     .spawn();
+    let server = match server {
+        Ok(server) => server,
+        Err(e) => {
+            println!("could not start {}: {}", binary, e);
+            process::exit(1);
+        }
+    };
+
+    for _ in 0..100 {
+        if TcpStream::connect(("127.0.0.1", port)).is_ok() {
+            return server;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    println!("{} did not start listening on port {}", binary, port);
+    process::exit(1);
+}
@@ -0,0 +1,192 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+use futures::Future;
+use futures::future::{ self, Loop };
+use tokio_core::net::TcpStream;
+use tokio_core::reactor::{ Handle, Timeout };
+use cache_codec::types::{ CacheCommand, CacheResponse };
+use command;
+use connection::{ pipeline, with_timeout, Pipeline };
+use error::ClientError;
+use retry::{ self, RetryPolicy };
+use Cacheable;
+
+/// A non-blocking client for use from code that already runs on a
+/// `tokio_core` reactor.
+///
+/// The connection is driven by a task spawned on the caller's reactor, and
+/// every method returns a future instead of waiting for the server. Requests
+/// are pipelined over the one connection. The client can be cloned to share
+/// that connection.
+///
+/// Request timeouts and retries are off until set with `request_timeout` and
+/// `retry_policy`. Retries reuse the same connection, so they help with slow
+/// or failing servers but not with a closed connection.
+#[derive(Clone)]
+pub struct AsyncCacheClient {
+    pipeline: Pipeline,
+    handle: Handle,
+    request_timeout: Option<Duration>,
+    retry_policy: RetryPolicy
+}
+
+impl AsyncCacheClient {
+    pub fn connect(address: &SocketAddr, handle: &Handle) -> Box<dyn Future<Item=Self, Error=ClientError>> {
+        let handle = handle.clone();
+        Box::new(TcpStream::connect(address, &handle).map(move |socket| {
+            let (pipeline, driver) = pipeline(socket);
+            handle.spawn(driver.map_err(|_| ()));
+            AsyncCacheClient {
+                pipeline: pipeline,
+                handle: handle,
+                request_timeout: None,
+                retry_policy: RetryPolicy::none()
+            }
+        }).map_err(ClientError::Io))
+    }
+
+    /// How long to wait for the response to each request before failing it
+    /// with `ClientError::Timeout`.
+    pub fn request_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = Some(timeout);
+        self
+    }
+
+    /// How to retry idempotent commands that time out or fail with a server
+    /// error.
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    /// Whether the connection is still open. Once it closes every request
+    /// fails and a new client should be connected.
+    pub fn is_alive(&self) -> bool {
+        self.pipeline.is_alive()
+    }
+
+    /// Fetches the cached value for `item`'s key, or `None` on a miss.
+    pub fn get<T: Cacheable + 'static>(&self, item: T) -> Box<dyn Future<Item=Option<T>, Error=ClientError>> {
+        Box::new(self.send_request(command::get(item.key()))
+            .then(command::found)
+            .map(move |found| found.map(|response| item.value_from_bytes(response.data))))
+    }
+
+    pub fn put<T: Cacheable + 'static>(&self, item: T) -> Box<dyn Future<Item=T, Error=ClientError>> {
+        self.put_entry(item, None)
+    }
+
+    /// Caches `item` so that it is no longer returned once `ttl` has elapsed.
+    pub fn put_with_ttl<T: Cacheable + 'static>(&self, item: T, ttl: Duration) -> Box<dyn Future<Item=T, Error=ClientError>> {
+        self.put_entry(item, Some(command::ttl(ttl)))
+    }
+
+    fn put_entry<T: Cacheable + 'static>(&self, item: T, ttl: Option<u64>) -> Box<dyn Future<Item=T, Error=ClientError>> {
+        let command = command::put(item.key(), item.value(), ttl);
+        Box::new(self.send_request(command)
+            .and_then(ClientError::check)
+            .map(move |_| item))
+    }
+
+    /// Removes `item`'s key, resolving to whether it was cached. Deletes are
+    /// not retried, as with `CacheClient::delete`.
+    pub fn delete<T: Cacheable + 'static>(&self, item: T) -> Box<dyn Future<Item=bool, Error=ClientError>> {
+        Box::new(self.send_request(command::delete(item.key()))
+            .then(command::found)
+            .map(|found| found.is_some()))
+    }
+
+    /// Fetches the server's usage counters, such as `entries` and `bytes`.
+    pub fn stats(&self) -> Box<dyn Future<Item=HashMap<String, u64>, Error=ClientError>> {
+        Box::new(self.send_request(command::stats())
+            .and_then(ClientError::check)
+            .and_then(command::parse_stats))
+    }
+
+    fn send_request(&self, cmd: CacheCommand) -> Box<dyn Future<Item=CacheResponse, Error=ClientError>> {
+        let retries = if command::is_idempotent(&cmd) { self.retry_policy.max_retries() } else { 0 };
+        let client = self.clone();
+
+        Box::new(future::loop_fn(0, move |attempt| {
+            let request = with_timeout(client.pipeline.send(cmd.clone()), client.request_timeout, &client.handle);
+            let client = client.clone();
+
+            request.then(move |result| -> Box<dyn Future<Item=Loop<CacheResponse, u32>, Error=ClientError>> {
+                if attempt == retries || !retry::is_transient(&result) {
+                    return Box::new(future::result(result.map(Loop::Break)));
+                }
+                let backoff = client.retry_policy.backoff(attempt + 1);
+                Box::new(future::result(Timeout::new(backoff, &client.handle))
+                    .flatten()
+                    .map(move |_| Loop::Continue(attempt + 1))
+                    .map_err(ClientError::Io))
+            })
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+    use futures::Future;
+    use tokio_core::reactor::Core;
+    use test_server::TestServer;
+    use retry::RetryPolicy;
+    use Entry;
+    use super::AsyncCacheClient;
+
+    fn entry(key: &str, value: &str) -> Entry {
+        Entry::new(key, value)
+    }
+
+    #[test]
+    fn runs_commands_on_callers_reactor() {
+        let server = TestServer::start();
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+
+        let client = core.run(AsyncCacheClient::connect(&server.address, &handle)).unwrap();
+        let work = client.put(entry("key", "value"))
+            .and_then(|_| client.get(entry("key", "")))
+            .and_then(|found| {
+                assert_eq!(found.unwrap().value, "value".to_string().into_bytes());
+                client.delete(entry("key", ""))
+            })
+            .and_then(|deleted| {
+                assert!(deleted);
+                client.get(entry("key", ""))
+            });
+
+        assert!(core.run(work).unwrap().is_none());
+        assert_eq!(server.connections(), 1);
+    }
+
+    #[test]
+    fn pipelines_concurrent_requests() {
+        let server = TestServer::start();
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+
+        let client = core.run(AsyncCacheClient::connect(&server.address, &handle)).unwrap();
+        let puts = (0..10).map(|i| client.put(entry(&format!("key{}", i), "value"))).collect::<Vec<_>>();
+        core.run(::futures::future::join_all(puts)).unwrap();
+
+        let gets = (0..10).map(|i| client.get(entry(&format!("key{}", i), ""))).collect::<Vec<_>>();
+        let found = core.run(::futures::future::join_all(gets)).unwrap();
+        assert!(found.iter().all(|entry| entry.is_some()));
+    }
+
+    #[test]
+    fn retries_server_errors() {
+        let server = TestServer::start_failing(2);
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+
+        let client = core.run(AsyncCacheClient::connect(&server.address, &handle)).unwrap()
+                         .retry_policy(RetryPolicy::new(2).initial_backoff(Duration::from_millis(1)));
+        let work = client.put(entry("key", "value")).and_then(|_| client.get(entry("key", "")));
+
+        assert!(core.run(work).unwrap().is_some());
+    }
+}
@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+use std::str;
+use std::time::Duration;
+use cache_codec::{ batch, counter };
+use cache_codec::types::{ CacheCommand, CacheResponse, Command };
+use error::ClientError;
+
+/// `ttl` in the whole seconds the protocol carries, rounded up and at least
+/// one so that a short or zero TTL still expires, since `0` on the wire means
+/// no TTL at all.
+pub fn ttl(ttl: Duration) -> u64 {
+    let round_up = if ttl.subsec_nanos() > 0 { 1 } else { 0 };
+    ttl.as_secs().saturating_add(round_up).max(1)
+}
+
+pub fn get(key: Vec<u8>) -> CacheCommand {
+    CacheCommand {
+        command: Command::GET,
+        key: key,
+        ttl: None,
+        version: None,
+        value: vec![],
+        length: 0
+    }
+}
+
+pub fn put(key: Vec<u8>, value: Vec<u8>, ttl: Option<u64>) -> CacheCommand {
+    let length = value.iter().len() as u64;
+    CacheCommand {
+        command: Command::PUT,
+        key: key,
+        ttl: ttl,
+        version: None,
+        value: value,
+        length: length
+    }
+}
+
+/// A put that goes ahead only if the key is not cached, for `Command::ADD`,
+/// or only if it is, for `Command::REPLACE`.
+pub fn put_if(command: Command, key: Vec<u8>, value: Vec<u8>, ttl: Option<u64>) -> CacheCommand {
+    CacheCommand { command: command, ..put(key, value, ttl) }
+}
+
+/// Adds `data` to the end of the value under `key` with `Command::APPEND`, or
+/// to the start with `Command::PREPEND`.
+pub fn extend(command: Command, key: Vec<u8>, data: Vec<u8>) -> CacheCommand {
+    CacheCommand { command: command, ..put(key, data, None) }
+}
+
+pub fn delete(key: Vec<u8>) -> CacheCommand {
+    CacheCommand {
+        command: Command::DEL,
+        key: key,
+        ttl: None,
+        version: None,
+        value: vec![],
+        length: 0
+    }
+}
+
+pub fn stats() -> CacheCommand {
+    CacheCommand {
+        command: Command::STATS,
+        key: vec![],
+        ttl: None,
+        version: None,
+        value: vec![],
+        length: 0
+    }
+}
+
+pub fn mget(keys: &[Vec<u8>]) -> CacheCommand {
+    let value = batch::encode_keys(keys);
+    CacheCommand {
+        command: Command::MGET,
+        key: vec![],
+        ttl: None,
+        version: None,
+        length: value.len() as u64,
+        value: value
+    }
+}
+
+pub fn mset(entries: &[(Vec<u8>, Vec<u8>)], ttl: Option<u64>) -> CacheCommand {
+    let value = batch::encode_entries(entries);
+    CacheCommand {
+        command: Command::MSET,
+        key: vec![],
+        ttl: ttl,
+        version: None,
+        length: value.len() as u64,
+        value: value
+    }
+}
+
+pub fn cas(key: Vec<u8>, value: Vec<u8>, ttl: Option<u64>, version: u64) -> CacheCommand {
+    let length = value.iter().len() as u64;
+    CacheCommand {
+        command: Command::CAS,
+        key: key,
+        ttl: ttl,
+        version: Some(version),
+        value: value,
+        length: length
+    }
+}
+
+/// Counts the counter under `key` up by `delta` with `Command::INCR`, or
+/// down with `Command::DECR`, creating it at `initial` if it is missing.
+pub fn count(command: Command, key: Vec<u8>, delta: u64, initial: Option<u64>, ttl: Option<u64>) -> CacheCommand {
+    let value = counter::encode_request(delta, initial);
+    CacheCommand {
+        command: command,
+        key: key,
+        ttl: ttl,
+        version: None,
+        length: value.len() as u64,
+        value: value
+    }
+}
+
+/// Whether sending `command` twice leaves the cache as sending it once would,
+/// so that it is safe to retry when the first response was lost.
+pub fn is_idempotent(command: &CacheCommand) -> bool {
+    match command.command {
+        Command::GET | Command::PUT | Command::STATS | Command::MGET | Command::MSET |
+        Command::REPLACE => true,
+        // A repeated delete leaves the cache the same, but finds the key gone
+        // and would report that it was never cached.
+        Command::DEL => false,
+        // A repeated swap finds the version it set itself and fails.
+        Command::CAS => false,
+        Command::INCR | Command::DECR => false,
+        // A repeated add finds the entry it made itself.
+        Command::ADD => false,
+        Command::APPEND | Command::PREPEND => false
+    }
+}
+
+/// Checks the response to a command that may miss, giving `None` when the
+/// key was not cached.
+pub fn found(result: Result<CacheResponse, ClientError>) -> Result<Option<CacheResponse>, ClientError> {
+    match result.and_then(ClientError::check) {
+        Ok(response) => Ok(Some(response)),
+        Err(ClientError::NotFound) => Ok(None),
+        Err(e) => Err(e)
+    }
+}
+
+/// Splits the response to `mget` or `mset` into the result for each key.
+pub fn batch_results(response: CacheResponse) -> Result<Vec<CacheResponse>, ClientError> {
+    batch::decode_responses(&response.data)
+          .map_err(|_| ClientError::Protocol("malformed batch response".to_string()))
+}
+
+/// Parses the `name value` lines the server sends in answer to `stats`.
+pub fn parse_stats(response: CacheResponse) -> Result<HashMap<String, u64>, ClientError> {
+    let text = str::from_utf8(&response.data)
+                   .map_err(|_| ClientError::Protocol("stats are not utf-8".to_string()))?;
+
+    text.lines().map(|line| {
+        let mut fields = line.split_whitespace();
+        match (fields.next(), fields.next().and_then(|value| value.parse().ok())) {
+            (Some(name), Some(value)) => Ok((name.to_string(), value)),
+            _ => Err(ClientError::Protocol(format!("malformed stat `{}`", line)))
+        }
+    }).collect()
+}
@@ -0,0 +1,311 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io;
+use std::net::SocketAddr;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::{ AtomicBool, Ordering };
+use std::sync::mpsc as std_mpsc;
+use std::thread;
+use std::time::Duration;
+use futures::{ future, BoxFuture, Future, Sink, Stream };
+use futures::sync::{ mpsc, oneshot };
+use tokio_core::io::Io;
+use tokio_core::net::TcpStream;
+use tokio_core::reactor::{ Core, Handle, Remote, Timeout };
+use cache_codec::types::{ CacheCommand, CacheResponse };
+use cache_codec::client_codec::CacheClientCodec;
+use error::ClientError;
+
+type Reply = oneshot::Sender<CacheResponse>;
+
+/// The sending half of a pipelined connection. It is cheap to clone and can
+/// be shared between threads; responses arrive once the driver returned
+/// alongside it by `pipeline` is running on a reactor.
+#[derive(Clone)]
+pub struct Pipeline {
+    requests: mpsc::UnboundedSender<(CacheCommand, Reply)>,
+    alive: Arc<AtomicBool>
+}
+
+impl Pipeline {
+    /// Whether the socket is still open. A pipeline that is no longer alive
+    /// fails every request and should be replaced.
+    pub fn is_alive(&self) -> bool {
+        self.alive.load(Ordering::SeqCst)
+    }
+
+    /// Whether `other` is a clone of this pipeline, sending over the same
+    /// socket.
+    pub fn is_same(&self, other: &Pipeline) -> bool {
+        Arc::ptr_eq(&self.alive, &other.alive)
+    }
+
+    pub fn send(&self, command: CacheCommand) -> BoxFuture<CacheResponse, ClientError> {
+        let (reply, response) = oneshot::channel();
+        if mpsc::UnboundedSender::send(&self.requests, (command, reply)).is_err() {
+            return future::err(ClientError::Io(closed())).boxed();
+        }
+
+        response.map_err(|_| ClientError::Io(closed())).boxed()
+    }
+}
+
+/// A long-lived connection to a cache server.
+///
+/// The connection is driven by a reactor on its own thread. Requests from any
+/// thread are forwarded to that reactor and pipelined over the one socket, so
+/// several may be in flight at once. The thread exits when the server closes
+/// the socket or once every clone of the connection has been dropped.
+#[derive(Clone)]
+pub struct Connection {
+    pipeline: Pipeline,
+    remote: Remote
+}
+
+impl Connection {
+    /// Connects to `address`, giving up after `connect_timeout` if one is set.
+    pub fn open(address: &SocketAddr, connect_timeout: Option<Duration>) -> io::Result<Connection> {
+        let address = *address;
+        let (connected, ready) = std_mpsc::channel();
+
+        thread::spawn(move || {
+            let mut core = match Core::new() {
+                Ok(core) => core,
+                Err(e) => return connected.send(Err(e)).unwrap_or(())
+            };
+            let socket = match core.run(connect(&address, connect_timeout, &core.handle())) {
+                Ok(socket) => socket,
+                Err(e) => return connected.send(Err(e)).unwrap_or(())
+            };
+            let (pipeline, driver) = pipeline(socket);
+            let _ = connected.send(Ok((pipeline, core.remote())));
+            let _ = core.run(driver);
+        });
+
+        match ready.recv() {
+            Ok(Ok((pipeline, remote))) => Ok(Connection { pipeline: pipeline, remote: remote }),
+            Ok(Err(e)) => Err(e),
+            Err(_) => Err(io::Error::new(io::ErrorKind::Other, "connection thread exited"))
+        }
+    }
+
+    pub fn is_alive(&self) -> bool {
+        self.pipeline.is_alive()
+    }
+
+    /// Whether `other` is a clone of this connection.
+    pub fn is_same(&self, other: &Connection) -> bool {
+        self.pipeline.is_same(&other.pipeline)
+    }
+
+    /// Sends `command` and blocks until its response arrives, or until
+    /// `timeout` has elapsed if one is set.
+    ///
+    /// The timer runs on the connection's reactor. A response that arrives
+    /// after its request timed out is read and discarded, so later requests on
+    /// the connection still receive their own responses.
+    pub fn call(&self, command: CacheCommand, timeout: Option<Duration>) -> Result<CacheResponse, ClientError> {
+        let response = self.pipeline.send(command);
+        let timeout = match timeout {
+            Some(timeout) => timeout,
+            None => return response.wait()
+        };
+
+        let (expire, expired) = oneshot::channel();
+        self.remote.spawn(move |handle| {
+            future::result(Timeout::new(timeout, handle)).flatten().then(move |_| {
+                expire.complete(ClientError::Timeout);
+                Ok(())
+            })
+        });
+        let expired = expired.then(|e| Err(e.unwrap_or_else(|_| ClientError::Io(closed()))));
+        response.select(expired).map(|(response, _)| response).map_err(|(e, _)| e).wait()
+    }
+}
+
+/// Fails `request` with `ClientError::Timeout` unless it completes within
+/// `timeout`.
+pub fn with_timeout<F>(request: F, timeout: Option<Duration>, handle: &Handle)
+                       -> Box<dyn Future<Item=F::Item, Error=ClientError>>
+    where F: Future<Error=ClientError> + 'static {
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return Box::new(request)
+    };
+
+    match Timeout::new(timeout, handle) {
+        Ok(timer) => {
+            let expired = timer.then(|_| -> Result<F::Item, ClientError> { Err(ClientError::Timeout) });
+            Box::new(request.select(expired).map(|(response, _)| response).map_err(|(e, _)| e))
+        },
+        Err(e) => Box::new(future::err(ClientError::Io(e)))
+    }
+}
+
+fn connect(address: &SocketAddr, timeout: Option<Duration>, handle: &Handle)
+           -> Box<dyn Future<Item=TcpStream, Error=io::Error>> {
+    let socket = TcpStream::connect(address, handle);
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return Box::new(socket)
+    };
+
+    match Timeout::new(timeout, handle) {
+        Ok(timer) => {
+            let expired = timer.and_then(|_| {
+                Err(io::Error::new(io::ErrorKind::TimedOut, "connect timed out"))
+            });
+            Box::new(socket.select(expired).map(|(socket, _)| socket).map_err(|(e, _)| e))
+        },
+        Err(e) => Box::new(future::err(e))
+    }
+}
+
+/// Splits `socket` into a `Pipeline` and the future that drives it.
+///
+/// The driver writes each command sent through the pipeline to the socket and
+/// completes its reply with the next response read back. The server answers
+/// in request order, so replies are queued first in, first out. Any replies
+/// still queued when the socket closes are dropped, which their callers see as
+/// a closed connection. The pipeline stops being alive as soon as either
+/// direction finishes, before the queued replies are dropped.
+pub fn pipeline(socket: TcpStream) -> (Pipeline, Box<dyn Future<Item=(), Error=io::Error>>) {
+    let (requests, incoming) = mpsc::unbounded::<(CacheCommand, Reply)>();
+    let alive = Arc::new(AtomicBool::new(true));
+    let sender = Pipeline { requests: requests, alive: alive.clone() };
+
+    let (sink, stream) = socket.framed(CacheClientCodec {}).split();
+    let pending = Rc::new(RefCell::new(VecDeque::<Reply>::new()));
+
+    let queued = pending.clone();
+    let commands = incoming.map(move |(command, reply)| {
+        queued.borrow_mut().push_back(reply);
+        command
+    }).map_err(|_| closed());
+    let writer_alive = alive.clone();
+    let writer = sink.send_all(commands).map(|_| ()).then(move |result| {
+        writer_alive.store(false, Ordering::SeqCst);
+        result
+    });
+
+    let reader = stream.for_each(move |response| {
+        if let Some(reply) = pending.borrow_mut().pop_front() {
+            reply.complete(response);
+        }
+        Ok(())
+    }).then(move |result| {
+        alive.store(false, Ordering::SeqCst);
+        result
+    });
+
+    (sender, Box::new(reader.select(writer).map(|_| ()).map_err(|(e, _)| e)))
+}
+
+fn closed() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "connection closed")
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{ Read, Write };
+    use std::net::TcpListener;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+    use tokio_core::io::{ Codec, EasyBuf };
+    use cache_codec::types::{ CacheCommand, CacheResponse, Command, CommandResult };
+    use cache_codec::server_codec::CacheServerCodec;
+    use error::ClientError;
+    use super::Connection;
+
+    // Accepts a single connection and answers `count` commands with their
+    // own keys, but only once all of them have arrived.
+    fn echo_server(count: usize) -> (::std::net::SocketAddr, thread::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (mut socket, _) = listener.accept().unwrap();
+            let mut codec = CacheServerCodec::default();
+            let mut buf = EasyBuf::new();
+            let mut commands = vec![];
+            while commands.len() < count {
+                let mut chunk = [0; 1024];
+                let read = socket.read(&mut chunk).unwrap();
+                buf.get_mut().extend_from_slice(&chunk[..read]);
+                while let Some(command) = codec.decode(&mut buf).unwrap() {
+                    commands.push(command);
+                }
+            }
+
+            let mut out = vec![];
+            for command in commands {
+                let response = CacheResponse {
+                    response_type: CommandResult::SUCCESS,
+                    version: None,
+                    length: command.key.iter().len() as u64,
+                    data: command.key
+                };
+                codec.encode(response, &mut out).unwrap();
+            }
+            socket.write_all(&out).unwrap();
+        });
+        (address, server)
+    }
+
+    fn get(key: &str) -> CacheCommand {
+        CacheCommand {
+            command: Command::GET,
+            key: key.to_string().into_bytes(),
+            ttl: None,
+            version: None,
+            value: vec![],
+            length: 0
+        }
+    }
+
+    #[test]
+    fn pipelines_concurrent_requests() {
+        let (address, server) = echo_server(4);
+        let connection = Arc::new(Connection::open(&address, None).unwrap());
+
+        let callers: Vec<_> = (0..4).map(|i| {
+            let connection = connection.clone();
+            thread::spawn(move || {
+                let key = format!("key{}", i);
+                let response = connection.call(get(&key), None).unwrap();
+                assert_eq!(response.data, key.into_bytes());
+            })
+        }).collect();
+
+        for caller in callers {
+            caller.join().unwrap();
+        }
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn reports_closed_connection() {
+        let (address, server) = echo_server(1);
+        let connection = Connection::open(&address, None).unwrap();
+
+        assert!(connection.call(get("key"), None).is_ok());
+        server.join().unwrap();
+        assert!(connection.call(get("key"), None).is_err());
+        assert!(!connection.is_alive());
+    }
+
+    #[test]
+    fn times_out_slow_responses() {
+        let (address, server) = echo_server(2);
+        let connection = Arc::new(Connection::open(&address, None).unwrap());
+
+        match connection.call(get("first"), Some(Duration::from_millis(50))) {
+            Err(ClientError::Timeout) => {},
+            other => panic!("unexpected result {:?}", other)
+        }
+        let response = connection.call(get("second"), Some(Duration::from_secs(5))).unwrap();
+        assert_eq!(response.data, "second".to_string().into_bytes());
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,29 @@
+use Cacheable;
+
+/// A plain key and value, for callers that have no type of their own to
+/// cache.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    pub key: Vec<u8>,
+    pub value: Vec<u8>
+}
+
+impl Entry {
+    pub fn new<K: Into<Vec<u8>>, V: Into<Vec<u8>>>(key: K, value: V) -> Self {
+        Entry { key: key.into(), value: value.into() }
+    }
+}
+
+impl Cacheable for Entry {
+    fn key(&self) -> Vec<u8> {
+        self.key.clone()
+    }
+
+    fn value(&self) -> Vec<u8> {
+        self.value.clone()
+    }
+
+    fn value_from_bytes(&self, val: Vec<u8>) -> Self {
+        Entry { key: self.key.clone(), value: val }
+    }
+}
@@ -0,0 +1,122 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use cache_codec::types::{ CacheResponse, CommandResult };
+
+#[derive(Debug)]
+pub enum ClientError {
+    /// The key is not cached, or its entry has expired.
+    NotFound,
+    /// The server's response could not be decoded.
+    Protocol(String),
+    /// The connection to the server failed.
+    Io(io::Error),
+    /// No response arrived within the request timeout.
+    Timeout,
+    /// The server rejected the command with the given result code.
+    Server(CommandResult)
+}
+
+impl ClientError {
+    /// Turns any result other than `SUCCESS` into the matching error.
+    pub fn check(response: CacheResponse) -> Result<CacheResponse, ClientError> {
+        match response.response_type {
+            CommandResult::SUCCESS => Ok(response),
+            CommandResult::NOT_FOUND | CommandResult::EXPIRED => Err(ClientError::NotFound),
+            code => Err(ClientError::Server(code))
+        }
+    }
+
+    /// Whether the same request might succeed if sent again.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            &ClientError::Io(_) | &ClientError::Timeout => true,
+            &ClientError::Server(CommandResult::SERVER_ERROR) => true,
+            _ => false
+        }
+    }
+}
+
+impl Clone for ClientError {
+    /// `io::Error` cannot be cloned, so a copy of `Io` keeps only its kind and
+    /// message.
+    fn clone(&self) -> Self {
+        match self {
+            &ClientError::NotFound => ClientError::NotFound,
+            &ClientError::Protocol(ref msg) => ClientError::Protocol(msg.clone()),
+            &ClientError::Io(ref e) => ClientError::Io(io::Error::new(e.kind(), e.to_string())),
+            &ClientError::Timeout => ClientError::Timeout,
+            &ClientError::Server(ref code) => ClientError::Server(code.clone())
+        }
+    }
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &ClientError::NotFound => write!(f, "key not found"),
+            &ClientError::Protocol(ref msg) => write!(f, "protocol error: {}", msg),
+            &ClientError::Io(ref e) => write!(f, "io error: {}", e),
+            &ClientError::Timeout => write!(f, "request timed out"),
+            &ClientError::Server(ref code) => write!(f, "server error: {:?}", code)
+        }
+    }
+}
+
+impl Error for ClientError {
+    fn description(&self) -> &str {
+        match self {
+            &ClientError::NotFound => "key not found",
+            &ClientError::Protocol(_) => "protocol error",
+            &ClientError::Io(_) => "io error",
+            &ClientError::Timeout => "request timed out",
+            &ClientError::Server(_) => "server error"
+        }
+    }
+}
+
+impl From<io::Error> for ClientError {
+    fn from(e: io::Error) -> Self {
+        ClientError::Io(e)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use cache_codec::types::{ CacheResponse, CommandResult };
+    use super::ClientError;
+
+    fn response(response_type: CommandResult) -> CacheResponse {
+        CacheResponse {
+            response_type: response_type,
+            version: None,
+            length: 0,
+            data: vec![]
+        }
+    }
+
+    #[test]
+    fn success_passes_check() {
+        assert!(ClientError::check(response(CommandResult::SUCCESS)).is_ok());
+    }
+
+    #[test]
+    fn misses_are_not_found() {
+        match ClientError::check(response(CommandResult::NOT_FOUND)) {
+            Err(ClientError::NotFound) => {},
+            other => panic!("unexpected result {:?}", other)
+        }
+        match ClientError::check(response(CommandResult::EXPIRED)) {
+            Err(ClientError::NotFound) => {},
+            other => panic!("unexpected result {:?}", other)
+        }
+    }
+
+    #[test]
+    fn other_codes_are_server_errors() {
+        match ClientError::check(response(CommandResult::VALUE_TOO_LARGE)) {
+            Err(ClientError::Server(CommandResult::VALUE_TOO_LARGE)) => {},
+            other => panic!("unexpected result {:?}", other)
+        }
+    }
+}
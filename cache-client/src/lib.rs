@@ -1,86 +1,677 @@
 extern crate futures;
+extern crate md5;
+extern crate rand;
 extern crate tokio_core;
 extern crate cache_codec;
+#[cfg(test)]
+extern crate tokio_service;
+#[cfg(test)]
+extern crate cache_server;
+
+mod async_client;
+mod command;
+mod connection;
+mod entry;
+mod error;
+mod pool;
+mod retry;
+mod ring;
+#[cfg(test)]
+mod test_server;
 
-use std::io;
-use std::net::{ ToSocketAddrs, SocketAddr };
+use std::collections::HashMap;
+use std::net::ToSocketAddrs;
 use std::marker::Sync;
-use tokio_core::io::{ Codec, EasyBuf };
-use tokio_core::net::TcpStream;
-use tokio_core::reactor::Core;
-use futures::Future;
-use cache_codec::types::{ CacheCommand, CacheResponse, Command };
-use cache_codec::client_codec::CacheClientCodec;
+use std::thread;
+use std::time::Duration;
+use cache_codec::counter;
+use cache_codec::types::{ CacheCommand, CacheResponse, Command, CommandResult };
+use pool::Pool;
+
+pub use async_client::AsyncCacheClient;
+pub use entry::Entry;
+pub use error::ClientError;
+pub use retry::RetryPolicy;
+pub use ring::Ring;
 
 pub trait Cacheable : Sync + Send {
-    fn key(&self) -> String;
+    fn key(&self) -> Vec<u8>;
     fn value(&self) -> Vec<u8>;
     fn value_from_bytes(&self, val: Vec<u8>) -> Self;
 }
 
+pub struct CacheClientBuilder {
+    addresses: Vec<String>,
+    pool_size: usize,
+    connect_timeout: Option<Duration>,
+    idle_timeout: Option<Duration>,
+    request_timeout: Option<Duration>,
+    retry_policy: RetryPolicy
+}
+
+impl CacheClientBuilder {
+    pub fn new(addr: String) -> Self {
+        CacheClientBuilder::with_servers(vec![addr])
+    }
+
+    /// A client spreading keys over several servers with a consistent hash of
+    /// the key, so that adding or removing a server moves only about
+    /// `1 / servers` of the keys. Every client sharing the servers should list
+    /// them the same way, though not necessarily in the same order.
+    pub fn with_servers(addrs: Vec<String>) -> Self {
+        CacheClientBuilder {
+            addresses: addrs,
+            pool_size: 4,
+            connect_timeout: None,
+            idle_timeout: None,
+            request_timeout: None,
+            retry_policy: RetryPolicy::none()
+        }
+    }
+
+    /// Number of connections kept open to each server. Defaults to 4.
+    pub fn pool_size(mut self, size: usize) -> Self {
+        self.pool_size = size;
+        self
+    }
+
+    /// How long to wait for a connection to be established. Defaults to the
+    /// operating system's timeout.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Connections left unused for this long are closed and reopened on next
+    /// use. By default connections are kept open indefinitely.
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// How long to wait for the response to each request before failing it
+    /// with `ClientError::Timeout`. By default requests wait indefinitely.
+    pub fn request_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = Some(timeout);
+        self
+    }
+
+    /// How to retry idempotent commands that time out or fail with a
+    /// connection or server error. By default nothing is retried.
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    pub fn build(self) -> Option<CacheClient> {
+        let pool_size = self.pool_size;
+        let connect_timeout = self.connect_timeout;
+        let idle_timeout = self.idle_timeout;
+        let request_timeout = self.request_timeout;
+        let retry_policy = self.retry_policy;
+        let ring = Ring::new(&self.addresses);
+
+        let pools: Option<Vec<Pool>> = self.addresses.iter().map(|address| {
+            address.to_socket_addrs()
+                .ok()
+                .and_then(|mut addresses| addresses.next())
+                .map(|address| Pool::new(address, pool_size, connect_timeout, idle_timeout))
+        }).collect();
+
+        pools.and_then(|pools| if pools.is_empty() { None } else { Some(pools) })
+            .map(|pools| CacheClient {
+                ring: ring,
+                pools: pools,
+                request_timeout: request_timeout,
+                retry_policy: retry_policy
+            })
+    }
+}
+
 pub struct CacheClient {
-    address: SocketAddr
+    pools: Vec<Pool>,
+    ring: Ring,
+    request_timeout: Option<Duration>,
+    retry_policy: RetryPolicy
 }
 
 impl CacheClient {
     pub fn new(addr: String) -> Option<Self> {
-        addr.to_socket_addrs()
-            .ok()
-            .and_then(|mut addresses| addresses.next())
-            .map(|address| CacheClient { address: address })
-    }
-
-    pub fn get<T: Cacheable + 'static>(&self, item: T) -> io::Result<T> {
-        let command = CacheCommand {
-            command: Command::GET,
-            key: item.key(),
-            value: vec![],
-            length: 0
-        };
-
-        self.send_request(command).map(move |response| item.value_from_bytes(response.data))
-    }
-
-    pub fn put<T: Cacheable + 'static>(&self, item: T) -> io::Result<T> {
-        let value = item.value();
-        let length = value.iter().len() as u64;
-        let command = CacheCommand {
-            command: Command::PUT,
-            key: item.key(),
-            value: value,
-            length: length
-        };
-
-        self.send_request(command).map(move |response| item.value_from_bytes(response.data))
-    }
-
-    fn send_request(&self, cmd: CacheCommand) -> io::Result<CacheResponse> {
-        let mut core = Core::new().unwrap();
-        let handle = core.handle();
-        let socket = TcpStream::connect(&self.address, &handle);
-
-        let mut codec = CacheClientCodec {};
-        let mut payload = vec![];
-        let _ = codec.encode(cmd, &mut payload);
-
-        core.run(
-            socket.and_then(|socket| {
-                tokio_core::io::write_all(socket, payload)
-            }).and_then(|(socket, _)| {
-                socket.shutdown(std::net::Shutdown::Write).expect("Couldn't shut down");
-                tokio_core::io::read_to_end(socket, vec![])
-            }).map(move |(_, data)| {
-                codec.decode(&mut EasyBuf::from(data))
-            }).and_then(|result| {
-                result.and_then(|option| option.ok_or(io::Error::new(io::ErrorKind::Other, "no result")))
-            })
-        )
+        CacheClientBuilder::new(addr).build()
+    }
+
+    /// Fetches the cached value for `item`'s key, or `None` on a miss.
+    pub fn get<T: Cacheable + 'static>(&self, item: T) -> Result<Option<T>, ClientError> {
+        command::found(self.send_request(command::get(item.key())))
+            .map(|found| found.map(|response| item.value_from_bytes(response.data)))
+    }
+
+    /// Fetches the cached value for `item`'s key along with the entry's
+    /// version, to pass to `cas`, or `None` on a miss.
+    pub fn get_versioned<T: Cacheable + 'static>(&self, item: T) -> Result<Option<(T, u64)>, ClientError> {
+        match command::found(self.send_request(command::get(item.key())))? {
+            Some(response) => match response.version {
+                Some(version) => Ok(Some((item.value_from_bytes(response.data), version))),
+                None => Err(ClientError::Protocol("response carries no version".to_string()))
+            },
+            None => Ok(None)
+        }
+    }
+
+    pub fn put<T: Cacheable + 'static>(&self, item: T) -> Result<T, ClientError> {
+        self.put_entry(item, None)
+    }
+
+    /// Caches `item` so that it is no longer returned once `ttl` has elapsed.
+    /// The server tracks expiry with one second granularity.
+    pub fn put_with_ttl<T: Cacheable + 'static>(&self, item: T, ttl: Duration) -> Result<T, ClientError> {
+        self.put_entry(item, Some(command::ttl(ttl)))
+    }
+
+    fn put_entry<T: Cacheable + 'static>(&self, item: T, ttl: Option<u64>) -> Result<T, ClientError> {
+        let command = command::put(item.key(), item.value(), ttl);
+        self.send_request(command).and_then(ClientError::check).map(move |_| item)
+    }
+
+    /// Caches `item` only if its key is not cached yet, returning false if it
+    /// is.
+    pub fn add<T: Cacheable + 'static>(&self, item: T) -> Result<bool, ClientError> {
+        self.put_if(Command::ADD, item, None)
+    }
+
+    /// As `add`, with the entry expiring once `ttl` has elapsed.
+    pub fn add_with_ttl<T: Cacheable + 'static>(&self, item: T, ttl: Duration) -> Result<bool, ClientError> {
+        self.put_if(Command::ADD, item, Some(command::ttl(ttl)))
+    }
+
+    /// Caches `item` only if its key is already cached, returning false if it
+    /// is not.
+    pub fn replace<T: Cacheable + 'static>(&self, item: T) -> Result<bool, ClientError> {
+        self.put_if(Command::REPLACE, item, None)
+    }
+
+    /// As `replace`, with the entry expiring once `ttl` has elapsed.
+    pub fn replace_with_ttl<T: Cacheable + 'static>(&self, item: T, ttl: Duration) -> Result<bool, ClientError> {
+        self.put_if(Command::REPLACE, item, Some(command::ttl(ttl)))
+    }
+
+    fn put_if<T: Cacheable + 'static>(&self, command: Command, item: T, ttl: Option<u64>) -> Result<bool, ClientError> {
+        let command = command::put_if(command, item.key(), item.value(), ttl);
+        match self.send_request(command).and_then(ClientError::check) {
+            Ok(_) => Ok(true),
+            Err(ClientError::Server(CommandResult::EXISTS)) | Err(ClientError::NotFound) => Ok(false),
+            Err(e) => Err(e)
+        }
+    }
+
+    /// Adds `item`'s value to the end of the cached one, returning false if
+    /// its key is not cached.
+    pub fn append<T: Cacheable + 'static>(&self, item: T) -> Result<bool, ClientError> {
+        self.extend(Command::APPEND, item)
+    }
+
+    /// Adds `item`'s value to the start of the cached one, returning false if
+    /// its key is not cached.
+    pub fn prepend<T: Cacheable + 'static>(&self, item: T) -> Result<bool, ClientError> {
+        self.extend(Command::PREPEND, item)
+    }
+
+    fn extend<T: Cacheable + 'static>(&self, command: Command, item: T) -> Result<bool, ClientError> {
+        command::found(self.send_request(command::extend(command, item.key(), item.value())))
+            .map(|found| found.is_some())
+    }
+
+    /// Caches `item` only if its entry is still at `version`, returning false
+    /// if it has been written since. An entry that has been removed or has
+    /// expired is `NotFound`.
+    pub fn cas<T: Cacheable + 'static>(&self, item: T, version: u64) -> Result<bool, ClientError> {
+        self.cas_entry(item, version, None)
+    }
+
+    /// As `cas`, with the entry expiring once `ttl` has elapsed.
+    pub fn cas_with_ttl<T: Cacheable + 'static>(&self, item: T, version: u64, ttl: Duration) -> Result<bool, ClientError> {
+        self.cas_entry(item, version, Some(command::ttl(ttl)))
+    }
+
+    fn cas_entry<T: Cacheable + 'static>(&self, item: T, version: u64, ttl: Option<u64>) -> Result<bool, ClientError> {
+        let command = command::cas(item.key(), item.value(), ttl, version);
+        match self.send_request(command).and_then(ClientError::check) {
+            Ok(_) => Ok(true),
+            Err(ClientError::Server(CommandResult::VERSION_MISMATCH)) => Ok(false),
+            Err(e) => Err(e)
+        }
+    }
+
+    /// Fetches every item's key with one request per server, giving each
+    /// item's cached value, or `None` on a miss, in the order of `items`. A
+    /// server that fails gives its error for each of its items.
+    pub fn get_many<T: Cacheable + 'static>(&self, items: Vec<T>) -> Vec<Result<Option<T>, ClientError>> {
+        let keys: Vec<Vec<u8>> = items.iter().map(|item| item.key()).collect();
+        let responses = self.send_batch(&keys, |indices| {
+            let batch: Vec<Vec<u8>> = indices.iter().map(|&index| keys[index].clone()).collect();
+            command::mget(&batch)
+        });
+
+        items.into_iter().zip(responses).map(|(item, response)| {
+            command::found(response).map(|found| found.map(|response| item.value_from_bytes(response.data)))
+        }).collect()
+    }
+
+    /// Caches every item with one request per server, giving the outcome for
+    /// each in the order of `items`. A server that fails gives its error for
+    /// each of its items.
+    pub fn put_many<T: Cacheable + 'static>(&self, items: Vec<T>) -> Vec<Result<T, ClientError>> {
+        self.put_entries(items, None)
+    }
+
+    /// As `put_many`, with every entry expiring once `ttl` has elapsed.
+    pub fn put_many_with_ttl<T: Cacheable + 'static>(&self, items: Vec<T>, ttl: Duration) -> Vec<Result<T, ClientError>> {
+        self.put_entries(items, Some(command::ttl(ttl)))
+    }
+
+    fn put_entries<T: Cacheable + 'static>(&self, items: Vec<T>, ttl: Option<u64>) -> Vec<Result<T, ClientError>> {
+        let keys: Vec<Vec<u8>> = items.iter().map(|item| item.key()).collect();
+        let responses = self.send_batch(&keys, |indices| {
+            let batch: Vec<(Vec<u8>, Vec<u8>)> = indices.iter()
+                .map(|&index| (keys[index].clone(), items[index].value()))
+                .collect();
+            command::mset(&batch, ttl)
+        });
+
+        items.into_iter().zip(responses).map(|(item, response)| {
+            response.and_then(ClientError::check).map(move |_| item)
+        }).collect()
+    }
+
+    /// Removes `item`'s key, returning whether it was cached. Deletes are not
+    /// retried, since a retry would report a key the first attempt removed as
+    /// never cached.
+    pub fn delete<T: Cacheable + 'static>(&self, item: T) -> Result<bool, ClientError> {
+        command::found(self.send_request(command::delete(item.key())))
+            .map(|found| found.is_some())
+    }
+
+    /// Adds `delta` to the counter under `item`'s key, stopping at
+    /// `u64::MAX`, and returns its new value. A missing counter is `NotFound`,
+    /// and a value not made by a count is a `NOT_A_COUNTER` server error.
+    pub fn incr<T: Cacheable + 'static>(&self, item: T, delta: u64) -> Result<u64, ClientError> {
+        self.count(Command::INCR, item, delta, None, None)
+    }
+
+    /// Subtracts `delta` from the counter under `item`'s key, stopping at
+    /// zero, and returns its new value. A missing counter is `NotFound`.
+    pub fn decr<T: Cacheable + 'static>(&self, item: T, delta: u64) -> Result<u64, ClientError> {
+        self.count(Command::DECR, item, delta, None, None)
+    }
+
+    /// As `incr`, except that a missing counter is created at `initial`
+    /// instead, expiring once `ttl` has elapsed if one is given.
+    pub fn incr_or_init<T: Cacheable + 'static>(&self, item: T, delta: u64, initial: u64, ttl: Option<Duration>) -> Result<u64, ClientError> {
+        self.count(Command::INCR, item, delta, Some(initial), ttl.map(command::ttl))
+    }
+
+    /// As `decr`, except that a missing counter is created at `initial`
+    /// instead, expiring once `ttl` has elapsed if one is given.
+    pub fn decr_or_init<T: Cacheable + 'static>(&self, item: T, delta: u64, initial: u64, ttl: Option<Duration>) -> Result<u64, ClientError> {
+        self.count(Command::DECR, item, delta, Some(initial), ttl.map(command::ttl))
+    }
+
+    fn count<T: Cacheable + 'static>(&self, command: Command, item: T, delta: u64, initial: Option<u64>, ttl: Option<u64>) -> Result<u64, ClientError> {
+        let response = self.send_request(command::count(command, item.key(), delta, initial, ttl))
+                           .and_then(ClientError::check)?;
+        counter::decode_value(&response.data)
+                .ok_or(ClientError::Protocol("counter is not 8 bytes".to_string()))
+    }
+
+    /// Fetches the server's usage counters, such as `entries` and `bytes`.
+    /// With several servers, each counter is summed over all of them.
+    pub fn stats(&self) -> Result<HashMap<String, u64>, ClientError> {
+        let mut totals = HashMap::new();
+        for pool in self.pools.iter() {
+            let stats = self.send_to(pool, command::stats())
+                            .and_then(ClientError::check)
+                            .and_then(command::parse_stats)?;
+            for (name, value) in stats {
+                *totals.entry(name).or_insert(0) += value;
+            }
+        }
+        Ok(totals)
+    }
+
+    /// Sends `command` as it is to the server for its key and returns the
+    /// response, whatever its result code, for callers relaying commands.
+    pub fn call(&self, command: CacheCommand) -> Result<CacheResponse, ClientError> {
+        self.send_request(command)
+    }
+
+    fn send_request(&self, cmd: CacheCommand) -> Result<CacheResponse, ClientError> {
+        let pool = &self.pools[self.ring.server(&cmd.key)];
+        self.send_to(pool, cmd)
+    }
+
+    /// Sends the batch `command` builds from the indices of the keys held by
+    /// each server to that server, and puts the results back in key order.
+    fn send_batch<F>(&self, keys: &[Vec<u8>], command: F) -> Vec<Result<CacheResponse, ClientError>>
+        where F: Fn(&[usize]) -> CacheCommand {
+        let mut by_server: Vec<Vec<usize>> = self.pools.iter().map(|_| vec![]).collect();
+        for (index, key) in keys.iter().enumerate() {
+            by_server[self.ring.server(key)].push(index);
+        }
+
+        let mut responses: Vec<Option<Result<CacheResponse, ClientError>>> = keys.iter().map(|_| None).collect();
+        for (server, indices) in by_server.iter().enumerate().filter(|&(_, indices)| !indices.is_empty()) {
+            let results = self.send_to(&self.pools[server], command(indices))
+                              .and_then(ClientError::check)
+                              .and_then(command::batch_results)
+                              .and_then(|results| if results.len() == indices.len() {
+                                  Ok(results)
+                              } else {
+                                  Err(ClientError::Protocol("wrong number of results in batch".to_string()))
+                              });
+            match results {
+                Ok(results) => for (&index, result) in indices.iter().zip(results) {
+                    responses[index] = Some(Ok(result));
+                },
+                Err(e) => for &index in indices {
+                    responses[index] = Some(Err(e.clone()));
+                }
+            }
+        }
+        responses.into_iter().map(|response| response.unwrap()).collect()
+    }
+
+    fn send_to(&self, pool: &Pool, cmd: CacheCommand) -> Result<CacheResponse, ClientError> {
+        let retries = if command::is_idempotent(&cmd) { self.retry_policy.max_retries() } else { 0 };
+        let mut attempt = 0;
+
+        loop {
+            let result = pool.call(cmd.clone(), self.request_timeout);
+            if attempt == retries || !retry::is_transient(&result) {
+                return result;
+            }
+            attempt += 1;
+            thread::sleep(self.retry_policy.backoff(attempt));
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::net::TcpListener;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+    use command;
+    use test_server::TestServer;
+    use cache_codec::types::CommandResult;
+    use super::{ CacheClientBuilder, ClientError, Entry, RetryPolicy };
+
+    fn entry(key: &str, value: &str) -> Entry {
+        Entry::new(key, value)
+    }
+
     #[test]
     fn it_works() {
     }
+
+    #[test]
+    fn rounds_ttls_up_to_whole_seconds() {
+        assert_eq!(command::ttl(Duration::from_secs(0)), 1);
+        assert_eq!(command::ttl(Duration::from_millis(1)), 1);
+        assert_eq!(command::ttl(Duration::from_millis(500)), 1);
+        assert_eq!(command::ttl(Duration::from_secs(2)), 2);
+        assert_eq!(command::ttl(Duration::new(2, 1)), 3);
+        assert_eq!(command::ttl(Duration::new(u64::MAX, 1)), u64::MAX);
+    }
+
+    #[test]
+    fn round_trips_commands() {
+        let server = TestServer::start();
+        let client = CacheClientBuilder::new(server.address.to_string()).build().unwrap();
+
+        assert!(client.get(entry("key", "")).unwrap().is_none());
+        assert!(client.put(entry("key", "value")).is_ok());
+        assert_eq!(client.get(entry("key", "")).unwrap().unwrap().value,
+                   "value".to_string().into_bytes());
+        assert!(client.delete(entry("key", "")).unwrap());
+        assert!(!client.delete(entry("key", "")).unwrap());
+    }
+
+    #[test]
+    fn shares_pool_between_threads() {
+        let server = TestServer::start();
+        let client = Arc::new(CacheClientBuilder::new(server.address.to_string())
+                                  .pool_size(2)
+                                  .build()
+                                  .unwrap());
+
+        let workers: Vec<_> = (0..8).map(|i| {
+            let client = client.clone();
+            thread::spawn(move || {
+                let key = format!("key{}", i);
+                client.put(entry(&key, "value")).unwrap();
+                assert!(client.get(entry(&key, "")).unwrap().is_some());
+            })
+        }).collect();
+
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        assert!(server.connections() <= 2);
+    }
+
+    #[test]
+    fn fetches_stats() {
+        let server = TestServer::start();
+        let client = CacheClientBuilder::new(server.address.to_string()).build().unwrap();
+
+        client.put(entry("key", "value")).unwrap();
+        assert_eq!(client.stats().unwrap().get("entries"), Some(&1));
+    }
+
+    #[test]
+    fn retries_transient_failures() {
+        let server = TestServer::start_failing(2);
+        let policy = RetryPolicy::new(2).initial_backoff(Duration::from_millis(1));
+        let client = CacheClientBuilder::new(server.address.to_string())
+                         .retry_policy(policy)
+                         .build()
+                         .unwrap();
+
+        assert!(client.get(entry("key", "")).unwrap().is_none());
+    }
+
+    #[test]
+    fn gives_up_after_max_retries() {
+        let server = TestServer::start_failing(2);
+        let policy = RetryPolicy::new(1).initial_backoff(Duration::from_millis(1));
+        let client = CacheClientBuilder::new(server.address.to_string())
+                         .retry_policy(policy)
+                         .build()
+                         .unwrap();
+
+        match client.get(entry("key", "")) {
+            Err(ClientError::Server(_)) => {},
+            other => panic!("unexpected result {:?}", other.map(|found| found.is_some()))
+        }
+        assert!(client.get(entry("key", "")).unwrap().is_none());
+    }
+
+    #[test]
+    fn does_not_retry_deletes() {
+        let server = TestServer::start_failing(1);
+        let policy = RetryPolicy::new(2).initial_backoff(Duration::from_millis(1));
+        let client = CacheClientBuilder::new(server.address.to_string())
+                         .retry_policy(policy)
+                         .build()
+                         .unwrap();
+
+        match client.delete(entry("key", "")) {
+            Err(ClientError::Server(_)) => {},
+            other => panic!("unexpected result {:?}", other)
+        }
+        assert!(!client.delete(entry("key", "")).unwrap());
+    }
+
+    #[test]
+    fn spreads_keys_across_servers() {
+        let servers = vec![TestServer::start(), TestServer::start()];
+        let addresses = servers.iter().map(|server| server.address.to_string()).collect();
+        let client = CacheClientBuilder::with_servers(addresses).build().unwrap();
+
+        for i in 0..20 {
+            client.put(entry(&format!("key{}", i), "value")).unwrap();
+        }
+        for i in 0..20 {
+            assert!(client.get(entry(&format!("key{}", i), "")).unwrap().is_some());
+        }
+
+        assert_eq!(client.stats().unwrap().get("entries"), Some(&20));
+        for server in servers.iter() {
+            let single = CacheClientBuilder::new(server.address.to_string()).build().unwrap();
+            assert!(single.stats().unwrap()["entries"] > 0);
+        }
+    }
+
+    #[test]
+    fn batches_gets_and_puts() {
+        let servers = vec![TestServer::start(), TestServer::start()];
+        let addresses = servers.iter().map(|server| server.address.to_string()).collect();
+        let client = CacheClientBuilder::with_servers(addresses).build().unwrap();
+
+        let stored = client.put_many((0..10).map(|i| entry(&format!("key{}", i), &i.to_string())).collect());
+        assert!(stored.iter().all(|result| result.is_ok()));
+
+        let items = vec![entry("key7", ""), entry("missing", ""), entry("key2", "")];
+        let found: Vec<Option<Vec<u8>>> = client.get_many(items).into_iter()
+            .map(|result| result.unwrap().map(|item| item.value))
+            .collect();
+        assert_eq!(found, vec![Some(b"7".to_vec()), None, Some(b"2".to_vec())]);
+        assert!(client.get_many(Vec::<Entry>::new()).is_empty());
+    }
+
+    #[test]
+    fn batches_keep_results_from_servers_that_answer() {
+        let server = TestServer::start();
+        let down = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let client = CacheClientBuilder::with_servers(vec![server.address.to_string(), down.to_string()]).build().unwrap();
+        let keys: Vec<String> = (0..10).map(|i| format!("key{}", i)).collect();
+        let up = |key: &String| client.ring.server(key.as_bytes()) == 0;
+        assert!(keys.iter().any(&up) && !keys.iter().all(&up));
+
+        let stored = client.put_many(keys.iter().map(|key| entry(key, "value")).collect());
+        for (key, result) in keys.iter().zip(stored) {
+            match result {
+                Ok(_) => assert!(up(key)),
+                Err(ClientError::Io(_)) => assert!(!up(key)),
+                Err(e) => panic!("unexpected error: {}", e)
+            }
+        }
+
+        let found = client.get_many(keys.iter().map(|key| entry(key, "")).collect());
+        for (key, result) in keys.iter().zip(found) {
+            assert_eq!(result.is_ok(), up(key));
+        }
+    }
+
+    #[test]
+    fn swaps_only_unchanged_entries() {
+        let server = TestServer::start();
+        let client = CacheClientBuilder::new(server.address.to_string()).build().unwrap();
+        client.put(entry("key", "1")).unwrap();
+
+        let (_, version) = client.get_versioned(entry("key", "")).unwrap().unwrap();
+        client.put(entry("key", "2")).unwrap();
+        assert!(!client.cas(entry("key", "3"), version).unwrap());
+
+        let (found, version) = client.get_versioned(entry("key", "")).unwrap().unwrap();
+        assert_eq!(found.value, b"2".to_vec());
+        assert!(client.cas(entry("key", "3"), version).unwrap());
+        assert_eq!(client.get(entry("key", "")).unwrap().unwrap().value, b"3".to_vec());
+
+        match client.cas(entry("missing", "1"), version) {
+            Err(ClientError::NotFound) => {},
+            other => panic!("unexpected result {:?}", other)
+        }
+    }
+
+    #[test]
+    fn counts_atomically() {
+        let server = TestServer::start();
+        let client = Arc::new(CacheClientBuilder::new(server.address.to_string()).build().unwrap());
+
+        match client.incr(entry("hits", ""), 1) {
+            Err(ClientError::NotFound) => {},
+            other => panic!("unexpected result {:?}", other)
+        }
+        assert_eq!(client.incr_or_init(entry("hits", ""), 1, 0, None).unwrap(), 0);
+
+        let threads: Vec<_> = (0..4).map(|_| {
+            let client = client.clone();
+            thread::spawn(move || {
+                for _ in 0..25 {
+                    client.incr(entry("hits", ""), 1).unwrap();
+                }
+            })
+        }).collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(client.decr(entry("hits", ""), 1).unwrap(), 99);
+        assert_eq!(client.decr_or_init(entry("hits", ""), 500, 7, None).unwrap(), 0);
+    }
+
+    #[test]
+    fn adds_and_replaces_conditionally() {
+        let server = TestServer::start();
+        let client = CacheClientBuilder::new(server.address.to_string()).build().unwrap();
+
+        assert!(!client.replace(entry("key", "1")).unwrap());
+        assert!(client.add(entry("key", "1")).unwrap());
+        assert!(!client.add(entry("key", "2")).unwrap());
+        assert_eq!(client.get(entry("key", "")).unwrap().unwrap().value, b"1".to_vec());
+        assert!(client.replace(entry("key", "3")).unwrap());
+        assert_eq!(client.get(entry("key", "")).unwrap().unwrap().value, b"3".to_vec());
+    }
+
+    #[test]
+    fn appends_and_prepends() {
+        let server = TestServer::start();
+        let client = CacheClientBuilder::new(server.address.to_string()).build().unwrap();
+
+        assert!(!client.append(entry("key", "c")).unwrap());
+        client.put(entry("key", "b")).unwrap();
+        assert!(client.append(entry("key", "c")).unwrap());
+        assert!(client.prepend(entry("key", "a")).unwrap());
+        assert_eq!(client.get(entry("key", "")).unwrap().unwrap().value, b"abc".to_vec());
+    }
+
+    #[test]
+    fn counting_and_appending_keep_expiry() {
+        let server = TestServer::start();
+        let client = CacheClientBuilder::new(server.address.to_string()).build().unwrap();
+        let ttl = Duration::from_secs(1);
+
+        client.incr_or_init(entry("hits", ""), 1, 0, Some(ttl)).unwrap();
+        assert_eq!(client.incr_or_init(entry("hits", ""), 1, 0, None).unwrap(), 1);
+        client.put_with_ttl(entry("key", "a"), ttl).unwrap();
+        assert!(client.append(entry("key", "b")).unwrap());
+
+        thread::sleep(Duration::from_millis(1100));
+        assert!(client.get(entry("hits", "")).unwrap().is_none());
+        assert!(client.get(entry("key", "")).unwrap().is_none());
+    }
+
+    #[test]
+    fn counts_only_counters() {
+        let server = TestServer::start();
+        let client = CacheClientBuilder::new(server.address.to_string()).build().unwrap();
+
+        client.put(entry("key", "abcdefgh")).unwrap();
+        match client.incr(entry("key", ""), 1) {
+            Err(ClientError::Server(CommandResult::NOT_A_COUNTER)) => {},
+            other => panic!("unexpected result {:?}", other)
+        }
+    }
 }
@@ -0,0 +1,149 @@
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use std::time::{ Duration, Instant };
+use cache_codec::types::{ CacheCommand, CacheResponse };
+use connection::Connection;
+use error::ClientError;
+
+struct Slot {
+    connection: Connection,
+    last_used: Instant
+}
+
+/// A fixed number of connections to one server, shared by every thread using
+/// the client.
+///
+/// Requests are spread over the slots in turn. A slot is (re)connected when it
+/// is next used if it is empty, its connection has closed, or it has sat idle
+/// for longer than the idle timeout. A request that fails with an I/O error or
+/// times out empties its slot so that the following request reconnects,
+/// unless another request has already reconnected it.
+pub struct Pool {
+    address: SocketAddr,
+    connect_timeout: Option<Duration>,
+    idle_timeout: Option<Duration>,
+    slots: Vec<Mutex<Option<Slot>>>,
+    next: AtomicUsize
+}
+
+impl Pool {
+    pub fn new(address: SocketAddr,
+               size: usize,
+               connect_timeout: Option<Duration>,
+               idle_timeout: Option<Duration>) -> Self {
+        Pool {
+            address: address,
+            connect_timeout: connect_timeout,
+            idle_timeout: idle_timeout,
+            slots: (0..size.max(1)).map(|_| Mutex::new(None)).collect(),
+            next: AtomicUsize::new(0)
+        }
+    }
+
+    pub fn call(&self, command: CacheCommand, timeout: Option<Duration>) -> Result<CacheResponse, ClientError> {
+        let index = self.next.fetch_add(1, Ordering::Relaxed) % self.slots.len();
+        let connection = self.checkout(index)?;
+        let result = connection.call(command, timeout);
+
+        match result {
+            Err(ClientError::Io(_)) | Err(ClientError::Timeout) => self.discard(index, &connection),
+            _ => {}
+        }
+        result
+    }
+
+    /// Empties slot `index` if it still holds `failed`.
+    fn discard(&self, index: usize, failed: &Connection) {
+        let mut slot = self.slots[index].lock().unwrap();
+        if slot.as_ref().map_or(false, |slot| slot.connection.is_same(failed)) {
+            *slot = None;
+        }
+    }
+
+    fn checkout(&self, index: usize) -> Result<Connection, ClientError> {
+        let mut slot = self.slots[index].lock().unwrap();
+        let now = Instant::now();
+
+        let healthy = match *slot {
+            Some(ref slot) => {
+                slot.connection.is_alive() &&
+                    self.idle_timeout.map_or(true, |idle| now.duration_since(slot.last_used) < idle)
+            },
+            None => false
+        };
+        if !healthy {
+            *slot = None;
+            *slot = Some(Slot {
+                connection: Connection::open(&self.address, self.connect_timeout)?,
+                last_used: now
+            });
+        }
+
+        let slot = slot.as_mut().unwrap();
+        slot.last_used = now;
+        Ok(slot.connection.clone())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Duration;
+    use command;
+    use test_server::TestServer;
+    use super::Pool;
+
+    fn get() -> ::cache_codec::types::CacheCommand {
+        command::get("key".to_string().into_bytes())
+    }
+
+    #[test]
+    fn reuses_connections() {
+        let server = TestServer::start();
+        let pool = Pool::new(server.address, 2, None, None);
+
+        for _ in 0..10 {
+            assert!(pool.call(get(), None).is_ok());
+        }
+        assert_eq!(server.connections(), 2);
+    }
+
+    #[test]
+    fn reconnects_after_idle_timeout() {
+        let server = TestServer::start();
+        let pool = Pool::new(server.address, 1, None, Some(Duration::from_millis(20)));
+
+        assert!(pool.call(get(), None).is_ok());
+        assert!(pool.call(get(), None).is_ok());
+        thread::sleep(Duration::from_millis(40));
+        assert!(pool.call(get(), None).is_ok());
+        assert_eq!(server.connections(), 2);
+    }
+
+    #[test]
+    fn keeps_connections_reopened_after_a_failure() {
+        let server = TestServer::start();
+        let pool = Pool::new(server.address, 1, None, None);
+
+        let failed = pool.checkout(0).unwrap();
+        pool.discard(0, &failed);
+        let reopened = pool.checkout(0).unwrap();
+        pool.discard(0, &failed);
+
+        assert!(pool.checkout(0).unwrap().is_same(&reopened));
+        assert!(pool.call(get(), None).is_ok());
+        assert_eq!(server.connections(), 2);
+    }
+
+    #[test]
+    fn reports_failed_connect() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        drop(listener);
+        let pool = Pool::new(address, 1, Some(Duration::from_millis(200)), None);
+
+        assert!(pool.call(get(), None).is_err());
+    }
+}
@@ -0,0 +1,105 @@
+use std::cmp;
+use std::time::Duration;
+use rand::{ self, Rng };
+use cache_codec::types::{ CacheResponse, CommandResult };
+use error::ClientError;
+
+/// How often, and how patiently, idempotent commands are retried after a
+/// transient failure such as a timeout or a dropped connection.
+///
+/// The delay before each retry doubles from `initial_backoff` up to
+/// `max_backoff`. Half of each delay is fixed and the other half is random,
+/// so that clients which failed together do not all retry together.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    max_retries: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration
+}
+
+impl RetryPolicy {
+    pub fn new(max_retries: u32) -> Self {
+        RetryPolicy {
+            max_retries: max_retries,
+            initial_backoff: Duration::from_millis(50),
+            max_backoff: Duration::from_secs(2)
+        }
+    }
+
+    /// Never retry.
+    pub fn none() -> Self {
+        RetryPolicy::new(0)
+    }
+
+    pub fn initial_backoff(mut self, backoff: Duration) -> Self {
+        self.initial_backoff = backoff;
+        self
+    }
+
+    pub fn max_backoff(mut self, backoff: Duration) -> Self {
+        self.max_backoff = backoff;
+        self
+    }
+
+    pub fn max_retries(&self) -> u32 {
+        self.max_retries
+    }
+
+    /// The delay before retry number `retry`, counting from 1.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let ceiling = as_millis(self.max_backoff);
+        let exponent = cmp::min(retry.saturating_sub(1), 32);
+        let delay = cmp::min(as_millis(self.initial_backoff).saturating_mul(1 << exponent), ceiling);
+
+        let half = delay / 2;
+        let jitter = if delay > 0 { rand::thread_rng().gen_range(0, delay - half + 1) } else { 0 };
+        Duration::from_millis(half + jitter)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy::none()
+    }
+}
+
+/// Whether `result` failed in a way that sending the request again might fix.
+pub fn is_transient(result: &Result<CacheResponse, ClientError>) -> bool {
+    match result {
+        &Ok(ref response) => response.response_type == CommandResult::SERVER_ERROR,
+        &Err(ref e) => e.is_transient()
+    }
+}
+
+fn as_millis(duration: Duration) -> u64 {
+    duration.as_secs() * 1000 + (duration.subsec_nanos() / 1_000_000) as u64
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+    use super::RetryPolicy;
+
+    #[test]
+    fn backoff_doubles_within_jitter() {
+        let policy = RetryPolicy::new(5).initial_backoff(Duration::from_millis(100));
+
+        for _ in 0..20 {
+            let first = policy.backoff(1);
+            let third = policy.backoff(3);
+            assert!(first >= Duration::from_millis(50) && first <= Duration::from_millis(100));
+            assert!(third >= Duration::from_millis(200) && third <= Duration::from_millis(400));
+        }
+    }
+
+    #[test]
+    fn backoff_is_capped() {
+        let policy = RetryPolicy::new(50)
+                         .initial_backoff(Duration::from_millis(100))
+                         .max_backoff(Duration::from_millis(300));
+
+        for _ in 0..20 {
+            assert!(policy.backoff(40) <= Duration::from_millis(300));
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use md5;
+
+// Each server gets 40 MD5 digests, each split into 4 points, as in ketama.
+const DIGESTS_PER_SERVER: usize = 40;
+const POINTS_PER_DIGEST: usize = 4;
+
+/// A ketama compatible consistent hash ring.
+///
+/// Every server is placed at 160 points on a ring of `u32`s, taken from the
+/// MD5 digests of `"<server>-<n>"`. A key belongs to the first server point at
+/// or after the hash of the key, wrapping round at the end. Adding or removing
+/// a server only moves the keys between its points and the ones before them,
+/// about `1 / servers` of all keys.
+pub struct Ring {
+    points: Vec<(u32, usize)>,
+    servers: usize
+}
+
+impl Ring {
+    /// A ring over `servers`, whose names should be the same, and given in the
+    /// same form, on every client sharing them.
+    pub fn new(servers: &[String]) -> Self {
+        let mut points = Vec::with_capacity(servers.len() * DIGESTS_PER_SERVER * POINTS_PER_DIGEST);
+        for (index, server) in servers.iter().enumerate() {
+            for n in 0..DIGESTS_PER_SERVER {
+                let digest = md5::compute(format!("{}-{}", server, n).as_bytes());
+                for part in 0..POINTS_PER_DIGEST {
+                    points.push((point(&digest[part * 4..part * 4 + 4]), index));
+                }
+            }
+        }
+        points.sort();
+
+        Ring { points: points, servers: servers.len() }
+    }
+
+    /// The index in `servers` of the server responsible for `key`.
+    pub fn server(&self, key: &[u8]) -> usize {
+        if self.servers <= 1 {
+            return 0;
+        }
+
+        let hash = hash(key);
+        let at = match self.points.binary_search_by(|&(point, _)| point.cmp(&hash)) {
+            Ok(at) | Err(at) => at
+        };
+        self.points.get(at).unwrap_or(&self.points[0]).1
+    }
+}
+
+fn hash(key: &[u8]) -> u32 {
+    point(&md5::compute(key)[0..4])
+}
+
+fn point(bytes: &[u8]) -> u32 {
+    (bytes[3] as u32) << 24 | (bytes[2] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[0] as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ hash, Ring };
+
+    fn servers(count: usize) -> Vec<String> {
+        (0..count).map(|i| format!("10.0.0.{}:11211", i)).collect()
+    }
+
+    fn keys() -> Vec<Vec<u8>> {
+        (0..10000).map(|i| format!("key{}", i).into_bytes()).collect()
+    }
+
+    #[test]
+    fn hashes_keys_like_ketama() {
+        assert_eq!(hash(b""), 0xd98c1dd4);
+    }
+
+    #[test]
+    fn spreads_keys_evenly() {
+        let ring = Ring::new(&servers(4));
+        let mut counts = vec![0; 4];
+        for key in keys() {
+            counts[ring.server(&key)] += 1;
+        }
+
+        for count in counts {
+            assert!(count > 1500 && count < 3500, "uneven spread {}", count);
+        }
+    }
+
+    #[test]
+    fn adding_a_server_moves_few_keys() {
+        let before = Ring::new(&servers(4));
+        let after = Ring::new(&servers(5));
+
+        let moved = keys().iter().filter(|key| before.server(key) != after.server(key)).count();
+        assert!(moved < 3000, "moved {} keys", moved);
+        assert!(keys().iter().all(|key| {
+            before.server(key) == after.server(key) || after.server(key) == 4
+        }));
+    }
+
+    #[test]
+    fn removing_a_server_only_moves_its_keys() {
+        let all = servers(4);
+        let remaining: Vec<String> = all.iter().filter(|&server| *server != all[1]).cloned().collect();
+        let before = Ring::new(&all);
+        let after = Ring::new(&remaining);
+
+        for key in keys() {
+            let was = &all[before.server(&key)];
+            if *was != all[1] {
+                assert_eq!(&remaining[after.server(&key)], was);
+            }
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use std::io::{ Read, Write };
+use std::net::{ SocketAddr, TcpListener, TcpStream };
+use std::sync::Arc;
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use std::thread;
+use futures::Future;
+use tokio_core::io::{ Codec, EasyBuf };
+use tokio_service::Service;
+use cache_codec::types::{ CacheResponse, CommandResult };
+use cache_codec::server_codec::CacheServerCodec;
+use cache_server::replication::Role;
+use cache_server::service::CacheSrv;
+use cache_server::shards::Shards;
+use cache_server::storage::{ Limits, Policy };
+
+/// An in-process cache server for exercising the client. Commands are run by
+/// the real server's service over an unbounded store, while connections are
+/// served here so that they can be counted and made to fail.
+pub struct TestServer {
+    pub address: SocketAddr,
+    pub accepted: Arc<AtomicUsize>
+}
+
+impl TestServer {
+    pub fn start() -> TestServer {
+        TestServer::start_failing(0)
+    }
+
+    /// Starts a server that answers its first `failures` commands with
+    /// `SERVER_ERROR` instead of running them.
+    pub fn start_failing(failures: usize) -> TestServer {
+        let failures = Arc::new(AtomicUsize::new(failures));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let counter = accepted.clone();
+        let service = Arc::new(CacheSrv {
+            cache: Arc::new(Shards::new(1, Policy::Lru, Limits::new(None, None))),
+            max_value_size: u64::pow(2, 20),
+            oplog: None,
+            role: Role::Standalone
+        });
+
+        thread::spawn(move || {
+            for socket in listener.incoming() {
+                counter.fetch_add(1, Ordering::SeqCst);
+                let socket = socket.unwrap();
+                let service = service.clone();
+                let failures = failures.clone();
+                thread::spawn(move || serve(socket, service, failures));
+            }
+        });
+
+        TestServer { address: address, accepted: accepted }
+    }
+
+    pub fn connections(&self) -> usize {
+        self.accepted.load(Ordering::SeqCst)
+    }
+}
+
+fn serve(mut socket: TcpStream, service: Arc<CacheSrv>, failures: Arc<AtomicUsize>) {
+    let mut codec = CacheServerCodec::default();
+    let mut buf = EasyBuf::new();
+    let mut chunk = [0; 1024];
+
+    loop {
+        let read = match socket.read(&mut chunk) {
+            Ok(0) | Err(_) => return,
+            Ok(read) => read
+        };
+        buf.get_mut().extend_from_slice(&chunk[..read]);
+
+        let mut out = vec![];
+        while let Some(command) = codec.decode(&mut buf).unwrap() {
+            let response = if failures.load(Ordering::SeqCst) > 0 {
+                failures.fetch_sub(1, Ordering::SeqCst);
+                response(CommandResult::SERVER_ERROR)
+            } else {
+                service.call(command).wait().unwrap()
+            };
+            codec.encode(response, &mut out).unwrap();
+        }
+        if socket.write_all(&out).is_err() {
+            return;
+        }
+    }
+}
+
+fn response(response_type: CommandResult) -> CacheResponse {
+    CacheResponse {
+        response_type: response_type,
+        version: None,
+        length: 0,
+        data: vec![]
+    }
+}
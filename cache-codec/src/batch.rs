@@ -0,0 +1,140 @@
+//! Framing for the batches carried by `MGET` and `MSET`.
+//!
+//! A batch travels in the value of an ordinary command frame, whose key is
+//! left empty. An `MGET` value holds each key as a length and the bytes, and
+//! an `MSET` value each key followed by its value, the same way; lengths are
+//! 8 byte integers followed by `\n`, as in the rest of the frame. The `ttl` of
+//! an `MSET` applies to every entry.
+//!
+//! The response to either holds one result per key, in request order, each
+//! framed like a whole response: the result on its own line, then the
+//! version, the length and the data.
+
+use std::io;
+use types::{ CacheResponse, CommandResult };
+use helpers::{ FrameReader, encode_int };
+
+pub fn encode_keys(keys: &[Vec<u8>]) -> Vec<u8> {
+    let mut bytes = vec![];
+    for key in keys {
+        push_field(&mut bytes, key);
+    }
+    bytes
+}
+
+pub fn decode_keys(bytes: &[u8]) -> io::Result<Vec<Vec<u8>>> {
+    let mut reader = FrameReader::new(bytes);
+    let mut keys = vec![];
+    while reader.position() < bytes.len() {
+        keys.push(field(&mut reader)?);
+    }
+    Ok(keys)
+}
+
+pub fn encode_entries(entries: &[(Vec<u8>, Vec<u8>)]) -> Vec<u8> {
+    let mut bytes = vec![];
+    for &(ref key, ref value) in entries {
+        push_field(&mut bytes, key);
+        push_field(&mut bytes, value);
+    }
+    bytes
+}
+
+pub fn decode_entries(bytes: &[u8]) -> io::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    let mut reader = FrameReader::new(bytes);
+    let mut entries = vec![];
+    while reader.position() < bytes.len() {
+        let key = field(&mut reader)?;
+        let value = field(&mut reader)?;
+        entries.push((key, value));
+    }
+    Ok(entries)
+}
+
+pub fn encode_responses(responses: &[CacheResponse]) -> Vec<u8> {
+    let mut bytes = vec![];
+    for response in responses {
+        bytes.extend(response.response_type.as_bytes());
+        bytes.push(b'\n');
+        bytes.extend(encode_int(response.version.unwrap_or(0)));
+        bytes.push(b'\n');
+        push_field(&mut bytes, &response.data);
+    }
+    bytes
+}
+
+pub fn decode_responses(bytes: &[u8]) -> io::Result<Vec<CacheResponse>> {
+    let mut reader = FrameReader::new(bytes);
+    let mut responses = vec![];
+    while reader.position() < bytes.len() {
+        let response_type = complete(reader.line(|bytes| CommandResult::from_bytes(bytes)))?;
+        let version = complete(reader.int())?;
+        let data = field(&mut reader)?;
+        responses.push(CacheResponse {
+            response_type: response_type,
+            version: if version == 0 { None } else { Some(version) },
+            length: data.len() as u64,
+            data: data
+        });
+    }
+    Ok(responses)
+}
+
+fn push_field(bytes: &mut Vec<u8>, field: &[u8]) {
+    bytes.extend(encode_int(field.len() as u64));
+    bytes.push(b'\n');
+    bytes.extend(field);
+}
+
+fn field(reader: &mut FrameReader) -> io::Result<Vec<u8>> {
+    let length = complete(reader.int())?;
+    complete(reader.bytes(length as usize)).map(|bytes| bytes.to_vec())
+}
+
+/// A batch arrives whole inside its frame, so a field running past the end is
+/// malformed rather than still to come.
+fn complete<T>(field: io::Result<Option<T>>) -> io::Result<T> {
+    field.and_then(|field| {
+        field.ok_or(io::Error::new(io::ErrorKind::InvalidData, "truncated batch"))
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use types::{ CacheResponse, CommandResult };
+    use super::{ decode_entries, decode_keys, decode_responses, encode_entries, encode_keys, encode_responses };
+
+    #[test]
+    fn keys_symmetry() {
+        let keys = vec![b"one".to_vec(), vec![], vec![b'\n', 0]];
+        assert_eq!(decode_keys(&encode_keys(&keys)).unwrap(), keys);
+    }
+
+    #[test]
+    fn entries_symmetry() {
+        let entries = vec![(b"one".to_vec(), b"1".to_vec()), (b"two".to_vec(), vec![])];
+        assert_eq!(decode_entries(&encode_entries(&entries)).unwrap(), entries);
+    }
+
+    #[test]
+    fn responses_symmetry() {
+        let responses = vec![
+            CacheResponse { response_type: CommandResult::SUCCESS, version: Some(3), length: 5, data: b"value".to_vec() },
+            CacheResponse { response_type: CommandResult::NOT_FOUND, version: None, length: 0, data: vec![] }
+        ];
+
+        let decoded = decode_responses(&encode_responses(&responses)).unwrap();
+        assert_eq!(decoded.len(), 2);
+        assert_eq!(decoded[0].response_type, CommandResult::SUCCESS);
+        assert_eq!(decoded[0].version, Some(3));
+        assert_eq!(decoded[0].data, b"value".to_vec());
+        assert_eq!(decoded[1].response_type, CommandResult::NOT_FOUND);
+    }
+
+    #[test]
+    fn rejects_truncated_batch() {
+        let bytes = encode_keys(&[b"key".to_vec()]);
+        assert!(decode_keys(&bytes[..bytes.len() - 1]).is_err());
+        assert!(decode_entries(&bytes).is_err());
+    }
+}
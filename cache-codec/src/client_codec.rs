@@ -1,8 +1,7 @@
 use std::io;
 use tokio_core::io::{ Codec, EasyBuf };
-use byteorder::{ ByteOrder, BigEndian };
 use types::{ CacheCommand, CommandResult, CacheResponse };
-use helpers::{ parse_bytes, encode_int };
+use helpers::{ FrameReader, encode_int };
 
 pub struct CacheClientCodec {}
 
@@ -11,27 +10,40 @@ impl Codec for CacheClientCodec {
     type Out = CacheCommand;
 
     fn decode(&mut self, buf: &mut EasyBuf) -> io::Result<Option<Self::In>> {
-        let response_type = parse_bytes(buf, |bytes| CommandResult::from_bytes(bytes));
-        let length = parse_bytes(buf, |bytes| Some(BigEndian::read_u64(bytes)));
-        let mut data: Vec<u8> = vec![];
-
-        if let (Some(response), Some(l)) = (response_type, length) {
-            data.extend_from_slice(buf.drain_to(l as usize).as_slice());
-            Ok(Some(CacheResponse {
-                response_type: response,
-                length: l,
-                data: data
-            }))
-        } else {
-            Err(io::Error::new(io::ErrorKind::Other, "invalid response"))
-        }
+        let (response, frame_length) = {
+            let mut reader = FrameReader::new(buf.as_slice());
+            let response_type = try_frame!(reader.line(|bytes| CommandResult::from_bytes(bytes)));
+            let version = try_frame!(reader.int());
+            let length = try_frame!(reader.int());
+            let data = try_frame!(reader.bytes(length as usize));
+
+            (CacheResponse {
+                response_type: response_type,
+                version: if version == 0 { None } else { Some(version) },
+                length: length,
+                data: data.to_vec()
+            }, reader.position())
+        };
+
+        buf.drain_to(frame_length);
+        Ok(Some(response))
     }
 
     fn encode(&mut self, msg: Self::Out, buf: &mut Vec<u8>) -> io::Result<()> {
         buf.extend(msg.command.as_bytes());
         buf.push(b'\n');
 
-        buf.extend(msg.key.as_bytes());
+        let key_length = encode_int(msg.key.iter().len() as u64);
+        buf.extend(key_length.as_slice());
+        buf.push(b'\n');
+        buf.extend(msg.key);
+
+        let ttl = encode_int(msg.ttl.unwrap_or(0));
+        buf.extend(ttl.as_slice());
+        buf.push(b'\n');
+
+        let version = encode_int(msg.version.unwrap_or(0));
+        buf.extend(version.as_slice());
         buf.push(b'\n');
 
         let length = encode_int(msg.length);
@@ -56,14 +68,57 @@ mod test {
 
     #[test]
     fn encodes_put_command() {
-        let key = "key".to_string();
+        let key = "key".to_string().into_bytes();
+        let value = "value".to_string().into_bytes();
+        let length = value.iter().len() as u64;
+        let mut length_as_bytes = vec![0; 8];
+        BigEndian::write_u64(&mut length_as_bytes, length);
+
+        let command = CacheCommand {
+            key: key.clone(),
+            ttl: None,
+            version: None,
+            value: value.clone(),
+            length: length,
+            command: Command::PUT
+        };
+
+        let mut bytes = vec![];
+        let mut encoder = CacheClientCodec {};
+        let result = encoder.encode(command, &mut bytes);
+
+        let mut expected = vec![];
+        expected.extend("put".to_string().into_bytes());
+        expected.push(b'\n');
+        expected.extend(vec![0, 0, 0, 0, 0, 0, 0, 3]);
+        expected.push(b'\n');
+        expected.extend(key);
+        expected.extend(vec![0; 8]);
+        expected.push(b'\n');
+        expected.extend(vec![0; 8]);
+        expected.push(b'\n');
+        expected.extend(length_as_bytes);
+        expected.push(b'\n');
+        expected.extend(value);
+
+        assert!(result.is_ok());
+        assert_eq!(bytes, expected);
+    }
+
+    #[test]
+    fn encodes_put_command_with_ttl() {
+        let key = "key".to_string().into_bytes();
         let value = "value".to_string().into_bytes();
         let length = value.iter().len() as u64;
         let mut length_as_bytes = vec![0; 8];
         BigEndian::write_u64(&mut length_as_bytes, length);
+        let mut ttl_as_bytes = vec![0; 8];
+        BigEndian::write_u64(&mut ttl_as_bytes, 30);
 
         let command = CacheCommand {
             key: key.clone(),
+            ttl: Some(30),
+            version: None,
             value: value.clone(),
             length: length,
             command: Command::PUT
@@ -76,7 +131,12 @@ mod test {
         let mut expected = vec![];
         expected.extend("put".to_string().into_bytes());
         expected.push(b'\n');
-        expected.extend(key.into_bytes());
+        expected.extend(vec![0, 0, 0, 0, 0, 0, 0, 3]);
+        expected.push(b'\n');
+        expected.extend(key);
+        expected.extend(ttl_as_bytes);
+        expected.push(b'\n');
+        expected.extend(vec![0; 8]);
         expected.push(b'\n');
         expected.extend(length_as_bytes);
         expected.push(b'\n');
@@ -88,13 +148,15 @@ mod test {
 
     #[test]
     fn encodes_get_command() {
-        let key = "key".to_string();
+        let key = "key".to_string().into_bytes();
         let length = 0 as u64;
         let mut length_as_bytes = vec![0; 8];
         BigEndian::write_u64(&mut length_as_bytes, length);
 
         let command = CacheCommand {
             key: key.clone(),
+            ttl: None,
+            version: None,
             value: vec![],
             length: length,
             command: Command::GET
@@ -107,7 +169,49 @@ mod test {
         let mut expected = vec![];
         expected.extend("get".to_string().into_bytes());
         expected.push(b'\n');
-        expected.extend(key.into_bytes());
+        expected.extend(vec![0, 0, 0, 0, 0, 0, 0, 3]);
+        expected.push(b'\n');
+        expected.extend(key);
+        expected.extend(vec![0; 8]);
+        expected.push(b'\n');
+        expected.extend(vec![0; 8]);
+        expected.push(b'\n');
+        expected.extend(length_as_bytes);
+        expected.push(b'\n');
+
+        assert!(result.is_ok());
+        assert_eq!(bytes, expected);
+    }
+
+    #[test]
+    fn encodes_del_command() {
+        let key = "key".to_string().into_bytes();
+        let length = 0 as u64;
+        let mut length_as_bytes = vec![0; 8];
+        BigEndian::write_u64(&mut length_as_bytes, length);
+
+        let command = CacheCommand {
+            key: key.clone(),
+            ttl: None,
+            version: None,
+            value: vec![],
+            length: length,
+            command: Command::DEL
+        };
+
+        let mut bytes = vec![];
+        let mut encoder = CacheClientCodec {};
+        let result = encoder.encode(command, &mut bytes);
+
+        let mut expected = vec![];
+        expected.extend("del".to_string().into_bytes());
+        expected.push(b'\n');
+        expected.extend(vec![0, 0, 0, 0, 0, 0, 0, 3]);
+        expected.push(b'\n');
+        expected.extend(key);
+        expected.extend(vec![0; 8]);
+        expected.push(b'\n');
+        expected.extend(vec![0; 8]);
         expected.push(b'\n');
         expected.extend(length_as_bytes);
         expected.push(b'\n');
@@ -126,6 +230,8 @@ mod test {
         bytes.push(b'\n');
         let mut length = vec![0; 8];
         BigEndian::write_u64(&mut length, data.iter().len() as u64);
+        bytes.extend(vec![0; 8]);
+        bytes.push(b'\n');
         bytes.extend(length);
         bytes.push(b'\n');
         bytes.extend(data.clone());
@@ -149,6 +255,8 @@ mod test {
         bytes.push(b'\n');
         let mut length = vec![0; 8];
         BigEndian::write_u64(&mut length, data.iter().len() as u64);
+        bytes.extend(vec![0; 8]);
+        bytes.push(b'\n');
         bytes.extend(length);
         bytes.push(b'\n');
         bytes.extend(data.clone());
@@ -164,14 +272,16 @@ mod test {
 
     #[test]
     fn decodes_error_result() {
-        let response_type = CommandResult::FAILURE;
+        let response_type = CommandResult::NOT_FOUND;
         let data: Vec<u8> = vec![];
 
         let mut bytes = vec![];
-        bytes.extend("failure".to_string().as_bytes());
+        bytes.extend("not_found".to_string().as_bytes());
         bytes.push(b'\n');
         let mut length = vec![0; 8];
         BigEndian::write_u64(&mut length, data.iter().len() as u64);
+        bytes.extend(vec![0; 8]);
+        bytes.push(b'\n');
         bytes.extend(length);
         bytes.push(b'\n');
         bytes.extend(data.clone());
@@ -184,4 +294,32 @@ mod test {
         assert_eq!(decoded.length, data.iter().len() as u64);
         assert_eq!(decoded.data, data);
     }
+
+    #[test]
+    fn waits_for_complete_frame() {
+        let data: Vec<u8> = "cached data".to_string().as_bytes().to_vec();
+
+        let mut bytes = vec![];
+        bytes.extend("success".to_string().as_bytes());
+        bytes.push(b'\n');
+        let mut length = vec![0; 8];
+        BigEndian::write_u64(&mut length, data.iter().len() as u64);
+        bytes.extend(vec![0; 8]);
+        bytes.push(b'\n');
+        bytes.extend(length);
+        bytes.push(b'\n');
+        bytes.extend(data.clone());
+
+        let mut decoder = CacheClientCodec {};
+        for end in 0..bytes.iter().len() {
+            let mut buf = EasyBuf::from(bytes[..end].to_vec());
+            assert!(decoder.decode(&mut buf).unwrap().is_none());
+            assert_eq!(buf.len(), end);
+        }
+
+        let mut buf = EasyBuf::from(bytes);
+        let decoded = decoder.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(decoded.data, data);
+        assert_eq!(buf.len(), 0);
+    }
 }
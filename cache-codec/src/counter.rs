@@ -0,0 +1,64 @@
+//! Framing for the values carried by `INCR` and `DECR`.
+//!
+//! A counter is stored as an 8 byte integer, which is also what `INCR` and
+//! `DECR` answer with. Only a value created by `INCR` or `DECR` is a counter;
+//! counting any other value is `NOT_A_COUNTER`, whatever its length. Counts
+//! saturate: `INCR` stops at `u64::MAX` and `DECR` at zero.
+//!
+//! The command's value holds the amount to count by, and may be followed by
+//! an initial value for a counter that does not exist yet; the counter is
+//! then created with the initial value, and the command's `ttl`, rather than
+//! counted. Without one, counting a missing key is `NOT_FOUND`.
+
+use std::io;
+use byteorder::{ BigEndian, ByteOrder };
+use helpers::encode_int;
+
+pub fn encode_request(delta: u64, initial: Option<u64>) -> Vec<u8> {
+    let mut bytes = encode_int(delta);
+    if let Some(initial) = initial {
+        bytes.extend(encode_int(initial));
+    }
+    bytes
+}
+
+/// The amount to count by and the initial value, if there is one.
+pub fn decode_request(bytes: &[u8]) -> io::Result<(u64, Option<u64>)> {
+    match bytes.len() {
+        8 => Ok((BigEndian::read_u64(bytes), None)),
+        16 => Ok((BigEndian::read_u64(&bytes[..8]), Some(BigEndian::read_u64(&bytes[8..])))),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidData, "malformed counter request"))
+    }
+}
+
+pub fn encode_value(value: u64) -> Vec<u8> {
+    encode_int(value)
+}
+
+/// The counter in an `INCR` or `DECR` response or a counter's stored value,
+/// or `None` if the bytes are not 8 long.
+pub fn decode_value(bytes: &[u8]) -> Option<u64> {
+    if bytes.len() == 8 {
+        Some(BigEndian::read_u64(bytes))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ decode_request, decode_value, encode_request, encode_value };
+
+    #[test]
+    fn request_symmetry() {
+        assert_eq!(decode_request(&encode_request(5, None)).unwrap(), (5, None));
+        assert_eq!(decode_request(&encode_request(5, Some(10))).unwrap(), (5, Some(10)));
+        assert!(decode_request(&[0; 9]).is_err());
+    }
+
+    #[test]
+    fn value_symmetry() {
+        assert_eq!(decode_value(&encode_value(42)), Some(42));
+        assert_eq!(decode_value(b"42"), None);
+    }
+}
@@ -1,15 +1,80 @@
+use std::io;
 use byteorder::{ ByteOrder, BigEndian };
-use tokio_core::io::EasyBuf;
-
-pub fn parse_bytes<F, T>(buf: &mut EasyBuf, convert: F) -> Option<T>
-    where F: Fn(&[u8]) -> Option<T> {
-    buf.as_slice().iter().position(|&b| b == b'\n').and_then(|idx| {
-        let bytes = buf.drain_to(idx);
-        buf.drain_to(1);
-        convert(bytes.as_slice())
+
+// Unwraps the result of a `FrameReader` read, returning early from the
+// enclosing decoder when the frame is incomplete or malformed.
+macro_rules! try_frame {
+    ($e:expr) => (match $e {
+        Ok(Some(value)) => value,
+        Ok(None) => return Ok(None),
+        Err(e) => return Err(e)
     })
 }
 
+/// Longest command or result line accepted, newline excluded: the longest
+/// name with room to spare. Anything longer cannot be valid, so it is refused
+/// rather than buffered while waiting for a newline.
+pub const MAX_LINE_LENGTH: usize = 32;
+
+/// Reads the fields of a frame from the front of a buffer without consuming
+/// it. Each read returns `Ok(None)` when the buffer does not yet hold the
+/// whole field, so a decoder can wait for more data and start over.
+pub struct FrameReader<'a> {
+    bytes: &'a [u8],
+    position: usize
+}
+
+impl<'a> FrameReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        FrameReader { bytes: bytes, position: 0 }
+    }
+
+    /// Number of bytes read so far, i.e. the length of the frame once every
+    /// field has been read.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn line<F, T>(&mut self, convert: F) -> io::Result<Option<T>>
+        where F: Fn(&[u8]) -> Option<T> {
+        let rest = &self.bytes[self.position..];
+        match rest.iter().take(MAX_LINE_LENGTH + 1).position(|&b| b == b'\n') {
+            None if rest.len() > MAX_LINE_LENGTH => Err(invalid_frame()),
+            Some(idx) => {
+                self.position += idx + 1;
+                convert(&rest[..idx]).map(Some).ok_or(invalid_frame())
+            },
+            None => Ok(None)
+        }
+    }
+
+    pub fn int(&mut self) -> io::Result<Option<u64>> {
+        let rest = &self.bytes[self.position..];
+        if rest.len() < 9 {
+            Ok(None)
+        } else if rest[8] != b'\n' {
+            Err(invalid_frame())
+        } else {
+            self.position += 9;
+            Ok(Some(BigEndian::read_u64(&rest[..8])))
+        }
+    }
+
+    pub fn bytes(&mut self, length: usize) -> io::Result<Option<&'a [u8]>> {
+        let rest = &self.bytes[self.position..];
+        if rest.len() < length {
+            Ok(None)
+        } else {
+            self.position += length;
+            Ok(Some(&rest[..length]))
+        }
+    }
+}
+
+fn invalid_frame() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "invalid frame")
+}
+
 pub fn encode_int(i: u64) -> Vec<u8> {
     let mut length = vec![0; 8];
     BigEndian::write_u64(&mut length, i);
@@ -2,9 +2,12 @@ extern crate tokio_core;
 extern crate byteorder;
 
 pub mod types;
+#[macro_use]
 mod helpers;
 pub mod server_codec;
 pub mod client_codec;
+pub mod batch;
+pub mod counter;
 
 #[cfg(test)]
 mod test {
@@ -21,13 +24,15 @@ mod test {
     fn cache_command_symmetry() {
         let command = CacheCommand {
             command: Command::PUT,
-            key: "key".to_string(),
+            key: "key".to_string().into_bytes(),
+            ttl: Some(30),
+            version: Some(7),
             value: "value".to_string().as_bytes().to_vec(),
             length: "value".to_string().into_bytes().iter().len() as u64
         };
 
         let mut encoder = CacheClientCodec {};
-        let mut decoder = CacheServerCodec {};
+        let mut decoder = CacheServerCodec::default();
         let mut bytes = vec![];
 
         let _ = encoder.encode(command, &mut bytes);
@@ -35,21 +40,49 @@ mod test {
 
         let decoded = result.unwrap().unwrap();
         assert_eq!(decoded.command, Command::PUT);
-        assert_eq!(decoded.key, "key".to_string());
+        assert_eq!(decoded.key, "key".to_string().into_bytes());
+        assert_eq!(decoded.ttl, Some(30));
+        assert_eq!(decoded.version, Some(7));
         assert_eq!(decoded.value, "value".to_string().as_bytes().to_vec());
         assert_eq!(decoded.length, "value".to_string().into_bytes().iter().len() as u64);
 
     }
 
+    #[test]
+    fn binary_key_symmetry() {
+        let key = vec![b'k', b'\n', 0xff, 0, b'\n'];
+        let command = CacheCommand {
+            command: Command::GET,
+            key: key.clone(),
+            ttl: None,
+            version: None,
+            value: vec![],
+            length: 0
+        };
+
+        let mut encoder = CacheClientCodec {};
+        let mut decoder = CacheServerCodec::default();
+        let mut bytes = vec![];
+
+        let _ = encoder.encode(command, &mut bytes);
+        let result = decoder.decode(&mut EasyBuf::from(bytes));
+
+        let decoded = result.unwrap().unwrap();
+        assert_eq!(decoded.command, Command::GET);
+        assert_eq!(decoded.key, key);
+        assert_eq!(decoded.value, vec![]);
+    }
+
     #[test]
     fn cache_result_symmetry() {
         let response = CacheResponse {
             response_type: CommandResult::SUCCESS,
+            version: Some(7),
             data: "cached_data".to_string().as_bytes().to_vec(),
             length: "cached_data".to_string().as_bytes().iter().len() as u64
         };
 
-        let mut encoder = CacheServerCodec {};
+        let mut encoder = CacheServerCodec::default();
         let mut decoder = CacheClientCodec {};
         let mut bytes = vec![];
 
@@ -58,8 +91,48 @@ mod test {
 
         let decoded = result.unwrap().unwrap();
         assert_eq!(decoded.response_type, CommandResult::SUCCESS);
+        assert_eq!(decoded.version, Some(7));
         assert_eq!(decoded.data, "cached_data".to_string().as_bytes().to_vec());
         assert_eq!(decoded.length, "cached_data".to_string().as_bytes().iter().len() as u64);
 
     }
+
+    #[test]
+    fn result_code_symmetry() {
+        let results = vec![CommandResult::SUCCESS,
+                           CommandResult::NOT_FOUND,
+                           CommandResult::VALUE_TOO_LARGE,
+                           CommandResult::BAD_REQUEST,
+                           CommandResult::EXPIRED,
+                           CommandResult::SERVER_ERROR,
+                           CommandResult::READ_ONLY,
+                           CommandResult::VERSION_MISMATCH,
+                           CommandResult::NOT_A_COUNTER,
+                           CommandResult::EXISTS];
+
+        for result in results {
+            assert_eq!(CommandResult::from_bytes(&result.as_bytes()), Some(result));
+        }
+    }
+
+    #[test]
+    fn command_name_symmetry() {
+        let commands = vec![Command::PUT,
+                            Command::GET,
+                            Command::DEL,
+                            Command::STATS,
+                            Command::MGET,
+                            Command::MSET,
+                            Command::CAS,
+                            Command::INCR,
+                            Command::DECR,
+                            Command::ADD,
+                            Command::REPLACE,
+                            Command::APPEND,
+                            Command::PREPEND];
+
+        for command in commands {
+            assert_eq!(Command::from_bytes(&command.as_bytes()), Some(command));
+        }
+    }
 }
@@ -1,40 +1,75 @@
 use std::io;
-use std::str;
 use tokio_core::io::{ Codec, EasyBuf };
-use byteorder::{ ByteOrder, BigEndian };
 use types::{ Command, CacheCommand, CacheResponse };
-use helpers::{ parse_bytes, encode_int };
+use helpers::{ FrameReader, encode_int };
 
-pub struct CacheServerCodec {}
+/// Longest key or value `CacheServerCodec::default` accepts, in bytes.
+pub const DEFAULT_MAX_FRAME_LENGTH: u64 = 1 << 26;
+
+/// Decodes commands from clients. A key or value longer than
+/// `max_frame_length` is refused as `InvalidData` before any of it is read, so
+/// a bad length prefix cannot make the server buffer without limit.
+pub struct CacheServerCodec {
+    max_frame_length: u64
+}
+
+impl CacheServerCodec {
+    pub fn new(max_frame_length: u64) -> Self {
+        CacheServerCodec { max_frame_length: max_frame_length }
+    }
+
+    fn check_length(&self, length: u64) -> io::Result<u64> {
+        if length > self.max_frame_length {
+            Err(io::Error::new(io::ErrorKind::InvalidData, "frame too long"))
+        } else {
+            Ok(length)
+        }
+    }
+}
+
+impl Default for CacheServerCodec {
+    fn default() -> Self {
+        CacheServerCodec::new(DEFAULT_MAX_FRAME_LENGTH)
+    }
+}
 
 impl Codec for CacheServerCodec {
     type In = CacheCommand;
     type Out = CacheResponse;
 
     fn decode(&mut self, buf: &mut EasyBuf) -> io::Result<Option<Self::In>> {
-        if buf.len() == 0 { return Ok(None); }
-        let command = parse_bytes(buf, |bytes| Command::from_bytes(bytes));
-        let key = parse_bytes(buf, |bytes| str::from_utf8(bytes).ok().map(|s| s.to_string()));
-        let length = parse_bytes(buf, |bytes| Some(BigEndian::read_u64(bytes)));
-        let mut value: Vec<u8> = vec![];
-
-        if let (Some(cmd), Some(k), Some(l)) = (command, key, length) {
-            value.extend_from_slice(buf.drain_to(l as usize).as_slice());
-            Ok(Some(CacheCommand {
-                        command: cmd,
-                        key: k,
-                        length: l,
-                        value: value
-                    }))
-        } else {
-            Err(io::Error::new(io::ErrorKind::Other, "invalid request"))
-        }
+        let (command, frame_length) = {
+            let mut reader = FrameReader::new(buf.as_slice());
+            let command = try_frame!(reader.line(|bytes| Command::from_bytes(bytes)));
+            let key_length = self.check_length(try_frame!(reader.int()))?;
+            let key = try_frame!(reader.bytes(key_length as usize));
+            let ttl = try_frame!(reader.int());
+            let version = try_frame!(reader.int());
+            let length = self.check_length(try_frame!(reader.int()))?;
+            let value = try_frame!(reader.bytes(length as usize));
+
+            (CacheCommand {
+                command: command,
+                key: key.to_vec(),
+                ttl: if ttl == 0 { None } else { Some(ttl) },
+                version: if version == 0 { None } else { Some(version) },
+                length: length,
+                value: value.to_vec()
+            }, reader.position())
+        };
+
+        buf.drain_to(frame_length);
+        Ok(Some(command))
     }
 
     fn encode(&mut self, msg: Self::Out, buf: &mut Vec<u8>) -> io::Result<()> {
         buf.extend(msg.response_type.as_bytes());
         buf.push(b'\n');
 
+        let version = encode_int(msg.version.unwrap_or(0));
+        buf.extend(version.as_slice());
+        buf.push(b'\n');
+
         let length = encode_int(msg.length);
         buf.extend(length.as_slice());
         buf.push(b'\n');
@@ -46,12 +81,14 @@ impl Codec for CacheServerCodec {
 
 #[cfg(test)]
 mod test {
+    use std::io;
     use tokio_core::io::{ Codec, EasyBuf };
     use byteorder::{ BigEndian, ByteOrder };
     use types::{ Command,
                  CommandResult,
                  CacheResponse
                };
+    use helpers::encode_int;
     use super::CacheServerCodec;
 
     #[test]
@@ -66,22 +103,101 @@ mod test {
         let mut bytes = vec![];
         bytes.extend(command.to_string().into_bytes());
         bytes.push(b'\n');
+        bytes.extend(encode_int(key.len() as u64));
+        bytes.push(b'\n');
         bytes.extend(key.to_string().into_bytes());
+        bytes.extend(vec![0; 8]);
+        bytes.push(b'\n');
+        bytes.extend(vec![0; 8]);
         bytes.push(b'\n');
         bytes.extend(length_as_bytes);
         bytes.push(b'\n');
         bytes.extend(value);
 
-        let mut decoder = CacheServerCodec {};
+        let mut decoder = CacheServerCodec::default();
         let result = decoder.decode(&mut EasyBuf::from(bytes));
 
         let decoded = result.unwrap().unwrap();
         assert_eq!(decoded.command, Command::PUT);
-        assert_eq!(decoded.key, "key".to_string());
+        assert_eq!(decoded.key, "key".to_string().into_bytes());
+        assert_eq!(decoded.ttl, None);
+        assert_eq!(decoded.version, None);
         assert_eq!(decoded.length, 5);
         assert_eq!(decoded.value, "value".to_string().into_bytes());
     }
 
+    #[test]
+    fn decodes_put_command_with_ttl() {
+        let command = "put";
+        let key = "key";
+        let value = "value".to_string().into_bytes();
+        let length = value.iter().len() as u64;
+        let mut length_as_bytes = vec![0; 8];
+        BigEndian::write_u64(&mut length_as_bytes, length);
+        let mut ttl_as_bytes = vec![0; 8];
+        BigEndian::write_u64(&mut ttl_as_bytes, 30);
+
+        let mut bytes = vec![];
+        bytes.extend(command.to_string().into_bytes());
+        bytes.push(b'\n');
+        bytes.extend(encode_int(key.len() as u64));
+        bytes.push(b'\n');
+        bytes.extend(key.to_string().into_bytes());
+        bytes.extend(ttl_as_bytes);
+        bytes.push(b'\n');
+        bytes.extend(vec![0; 8]);
+        bytes.push(b'\n');
+        bytes.extend(length_as_bytes);
+        bytes.push(b'\n');
+        bytes.extend(value);
+
+        let mut decoder = CacheServerCodec::default();
+        let result = decoder.decode(&mut EasyBuf::from(bytes));
+
+        let decoded = result.unwrap().unwrap();
+        assert_eq!(decoded.command, Command::PUT);
+        assert_eq!(decoded.key, "key".to_string().into_bytes());
+        assert_eq!(decoded.ttl, Some(30));
+        assert_eq!(decoded.length, 5);
+        assert_eq!(decoded.value, "value".to_string().into_bytes());
+    }
+
+    #[test]
+    fn rejects_lengths_over_the_limit() {
+        let mut bytes = vec![];
+        bytes.extend("put".to_string().into_bytes());
+        bytes.push(b'\n');
+        bytes.extend(encode_int(3));
+        bytes.push(b'\n');
+        bytes.extend("key".to_string().into_bytes());
+        bytes.extend(vec![0; 8]);
+        bytes.push(b'\n');
+        bytes.extend(vec![0; 8]);
+        bytes.push(b'\n');
+        bytes.extend(encode_int(u64::MAX));
+        bytes.push(b'\n');
+
+        let mut decoder = CacheServerCodec::new(1024);
+        let error = decoder.decode(&mut EasyBuf::from(bytes)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        let mut key_too_long = vec![];
+        key_too_long.extend("get".to_string().into_bytes());
+        key_too_long.push(b'\n');
+        key_too_long.extend(encode_int(1025));
+        key_too_long.push(b'\n');
+        assert!(decoder.decode(&mut EasyBuf::from(key_too_long)).is_err());
+    }
+
+    #[test]
+    fn rejects_command_lines_without_a_newline() {
+        let mut decoder = CacheServerCodec::default();
+        let error = decoder.decode(&mut EasyBuf::from(vec![b'x'; 4096])).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        assert!(decoder.decode(&mut EasyBuf::from(b"repl".to_vec())).unwrap().is_none());
+    }
+
     #[test]
     fn decodes_get_command() {
         let command = "get";
@@ -93,21 +209,131 @@ mod test {
         let mut bytes = vec![];
         bytes.extend(command.to_string().into_bytes());
         bytes.push(b'\n');
+        bytes.extend(encode_int(key.len() as u64));
+        bytes.push(b'\n');
         bytes.extend(key.to_string().into_bytes());
+        bytes.extend(vec![0; 8]);
+        bytes.push(b'\n');
+        bytes.extend(vec![0; 8]);
         bytes.push(b'\n');
         bytes.extend(length_as_bytes);
         bytes.push(b'\n');
 
-        let mut decoder = CacheServerCodec {};
+        let mut decoder = CacheServerCodec::default();
         let result = decoder.decode(&mut EasyBuf::from(bytes));
 
         let decoded = result.unwrap().unwrap();
         assert_eq!(decoded.command, Command::GET);
-        assert_eq!(decoded.key, "key".to_string());
+        assert_eq!(decoded.key, "key".to_string().into_bytes());
+        assert_eq!(decoded.ttl, None);
+        assert_eq!(decoded.version, None);
+        assert_eq!(decoded.length, 0);
+        assert_eq!(decoded.value, vec![]);
+    }
+
+    #[test]
+    fn decodes_del_command() {
+        let command = "del";
+        let key = "key";
+        let length = 0 as u64;
+        let mut length_as_bytes = vec![0; 8];
+        BigEndian::write_u64(&mut length_as_bytes, length);
+
+        let mut bytes = vec![];
+        bytes.extend(command.to_string().into_bytes());
+        bytes.push(b'\n');
+        bytes.extend(encode_int(key.len() as u64));
+        bytes.push(b'\n');
+        bytes.extend(key.to_string().into_bytes());
+        bytes.extend(vec![0; 8]);
+        bytes.push(b'\n');
+        bytes.extend(vec![0; 8]);
+        bytes.push(b'\n');
+        bytes.extend(length_as_bytes);
+        bytes.push(b'\n');
+
+        let mut decoder = CacheServerCodec::default();
+        let result = decoder.decode(&mut EasyBuf::from(bytes));
+
+        let decoded = result.unwrap().unwrap();
+        assert_eq!(decoded.command, Command::DEL);
+        assert_eq!(decoded.key, "key".to_string().into_bytes());
+        assert_eq!(decoded.ttl, None);
+        assert_eq!(decoded.version, None);
         assert_eq!(decoded.length, 0);
         assert_eq!(decoded.value, vec![]);
     }
 
+    fn put_frame(key: &str, value: &[u8]) -> Vec<u8> {
+        let mut length_as_bytes = vec![0; 8];
+        BigEndian::write_u64(&mut length_as_bytes, value.iter().len() as u64);
+
+        let mut bytes = vec![];
+        bytes.extend("put".to_string().into_bytes());
+        bytes.push(b'\n');
+        bytes.extend(encode_int(key.len() as u64));
+        bytes.push(b'\n');
+        bytes.extend(key.to_string().into_bytes());
+        bytes.extend(vec![0; 8]);
+        bytes.push(b'\n');
+        bytes.extend(vec![0; 8]);
+        bytes.push(b'\n');
+        bytes.extend(length_as_bytes);
+        bytes.push(b'\n');
+        bytes.extend(value);
+        bytes
+    }
+
+    #[test]
+    fn waits_for_complete_frame() {
+        let bytes = put_frame("key", b"value");
+        let mut decoder = CacheServerCodec::default();
+
+        for end in 0..bytes.iter().len() {
+            let mut buf = EasyBuf::from(bytes[..end].to_vec());
+            assert!(decoder.decode(&mut buf).unwrap().is_none());
+            assert_eq!(buf.len(), end);
+        }
+
+        let mut buf = EasyBuf::from(bytes);
+        let decoded = decoder.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(decoded.key, "key".to_string().into_bytes());
+        assert_eq!(decoded.value, "value".to_string().into_bytes());
+        assert_eq!(buf.len(), 0);
+    }
+
+    #[test]
+    fn decodes_length_containing_newline_byte() {
+        let value = vec![b'x'; b'\n' as usize];
+        let mut decoder = CacheServerCodec::default();
+
+        let decoded = decoder.decode(&mut EasyBuf::from(put_frame("key", &value))).unwrap().unwrap();
+        assert_eq!(decoded.length, b'\n' as u64);
+        assert_eq!(decoded.value, value);
+    }
+
+    #[test]
+    fn decodes_consecutive_frames() {
+        let mut bytes = put_frame("first", b"1");
+        bytes.extend(put_frame("second", b"2"));
+        let mut buf = EasyBuf::from(bytes);
+        let mut decoder = CacheServerCodec::default();
+
+        let first = decoder.decode(&mut buf).unwrap().unwrap();
+        let second = decoder.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(first.key, "first".to_string().into_bytes());
+        assert_eq!(second.key, "second".to_string().into_bytes());
+        assert!(decoder.decode(&mut buf).unwrap().is_none());
+    }
+
+    #[test]
+    fn rejects_unknown_command() {
+        let mut decoder = CacheServerCodec::default();
+        let result = decoder.decode(&mut EasyBuf::from("bogus\n".to_string().into_bytes()));
+
+        assert!(result.is_err());
+    }
+
     #[test]
     fn encodes_success_result_with_payload() {
         let response_type = CommandResult::SUCCESS;
@@ -115,11 +341,12 @@ mod test {
 
         let response = CacheResponse {
             response_type: response_type,
+            version: None,
             length: data.iter().len() as u64,
             data: data.clone()
         };
 
-        let mut encoder = CacheServerCodec {};
+        let mut encoder = CacheServerCodec::default();
         let mut encoded: Vec<u8> = vec![];
         let result = encoder.encode(response, &mut encoded);
 
@@ -128,6 +355,8 @@ mod test {
         expected.push(b'\n');
         let mut length = vec![0; 8];
         BigEndian::write_u64(&mut length, data.iter().len() as u64);
+        expected.extend(vec![0; 8]);
+        expected.push(b'\n');
         expected.extend(length);
         expected.push(b'\n');
         expected.extend(data);
@@ -143,11 +372,12 @@ mod test {
 
         let response = CacheResponse {
             response_type: response_type,
+            version: None,
             length: 0 as u64,
             data: data.clone()
         };
 
-        let mut encoder = CacheServerCodec {};
+        let mut encoder = CacheServerCodec::default();
         let mut encoded: Vec<u8> = vec![];
         let result = encoder.encode(response, &mut encoded);
 
@@ -156,6 +386,8 @@ mod test {
         expected.push(b'\n');
         let mut length = vec![0; 8];
         BigEndian::write_u64(&mut length, 0 as u64);
+        expected.extend(vec![0; 8]);
+        expected.push(b'\n');
         expected.extend(length);
         expected.push(b'\n');
         expected.extend(data);
@@ -166,24 +398,27 @@ mod test {
 
     #[test]
     fn encodes_error_result() {
-        let response_type = CommandResult::FAILURE;
-        let data: Vec<u8> = "error message".to_string().as_bytes().to_vec();
+        let response_type = CommandResult::NOT_FOUND;
+        let data: Vec<u8> = vec![];
 
         let response = CacheResponse {
             response_type: response_type,
+            version: None,
             length: data.iter().len() as u64,
             data: data.clone()
         };
 
-        let mut encoder = CacheServerCodec {};
+        let mut encoder = CacheServerCodec::default();
         let mut encoded: Vec<u8> = vec![];
         let result = encoder.encode(response, &mut encoded);
 
         let mut expected = vec![];
-        expected.extend("failure".to_string().as_bytes());
+        expected.extend("not_found".to_string().as_bytes());
         expected.push(b'\n');
         let mut length = vec![0; 8];
         BigEndian::write_u64(&mut length, data.iter().len() as u64);
+        expected.extend(vec![0; 8]);
+        expected.push(b'\n');
         expected.extend(length);
         expected.push(b'\n');
         expected.extend(data);
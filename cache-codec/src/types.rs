@@ -1,9 +1,30 @@
 use std::str;
 
-#[derive(PartialEq, Eq, Debug)]
+#[derive(PartialEq, Eq, Clone, Debug)]
 pub enum Command {
     PUT,
-    GET
+    GET,
+    DEL,
+    STATS,
+    /// Gets a batch of keys at once; see `batch`.
+    MGET,
+    /// Puts a batch of entries at once; see `batch`.
+    MSET,
+    /// Puts the value only if the entry's version still matches the one in
+    /// the command.
+    CAS,
+    /// Adds to a counter; see `counter`.
+    INCR,
+    /// Subtracts from a counter, stopping at zero; see `counter`.
+    DECR,
+    /// Puts the value only if the key is not cached.
+    ADD,
+    /// Puts the value only if the key is cached.
+    REPLACE,
+    /// Adds the value to the end of the cached one.
+    APPEND,
+    /// Adds the value to the start of the cached one.
+    PREPEND
 }
 
 impl Command {
@@ -15,6 +36,28 @@ impl Command {
                         Some(Command::PUT)
                     } else if command == "get" {
                         Some(Command::GET)
+                    } else if command == "del" {
+                        Some(Command::DEL)
+                    } else if command == "stats" {
+                        Some(Command::STATS)
+                    } else if command == "mget" {
+                        Some(Command::MGET)
+                    } else if command == "mset" {
+                        Some(Command::MSET)
+                    } else if command == "cas" {
+                        Some(Command::CAS)
+                    } else if command == "incr" {
+                        Some(Command::INCR)
+                    } else if command == "decr" {
+                        Some(Command::DECR)
+                    } else if command == "add" {
+                        Some(Command::ADD)
+                    } else if command == "replace" {
+                        Some(Command::REPLACE)
+                    } else if command == "append" {
+                        Some(Command::APPEND)
+                    } else if command == "prepend" {
+                        Some(Command::PREPEND)
                     } else {
                         None
                     }
@@ -24,22 +67,54 @@ impl Command {
     pub fn as_bytes(&self) -> Vec<u8> {
         match self {
             &Command::PUT => "put".to_string().as_bytes().to_vec(),
-            &Command::GET => "get".to_string().as_bytes().to_vec()
+            &Command::GET => "get".to_string().as_bytes().to_vec(),
+            &Command::DEL => "del".to_string().as_bytes().to_vec(),
+            &Command::STATS => "stats".to_string().as_bytes().to_vec(),
+            &Command::MGET => "mget".to_string().as_bytes().to_vec(),
+            &Command::MSET => "mset".to_string().as_bytes().to_vec(),
+            &Command::CAS => "cas".to_string().as_bytes().to_vec(),
+            &Command::INCR => "incr".to_string().as_bytes().to_vec(),
+            &Command::DECR => "decr".to_string().as_bytes().to_vec(),
+            &Command::ADD => "add".to_string().as_bytes().to_vec(),
+            &Command::REPLACE => "replace".to_string().as_bytes().to_vec(),
+            &Command::APPEND => "append".to_string().as_bytes().to_vec(),
+            &Command::PREPEND => "prepend".to_string().as_bytes().to_vec()
         }
     }
 }
 
-#[derive(PartialEq, Eq, Debug)]
+#[allow(non_camel_case_types)]
+#[derive(PartialEq, Eq, Clone, Debug)]
 pub enum CommandResult {
     SUCCESS,
-    FAILURE
+    NOT_FOUND,
+    VALUE_TOO_LARGE,
+    BAD_REQUEST,
+    EXPIRED,
+    SERVER_ERROR,
+    /// The server is a replica and only answers reads.
+    READ_ONLY,
+    /// The entry has been written since the version given to `CAS`.
+    VERSION_MISMATCH,
+    /// The entry given to `INCR` or `DECR` does not hold a counter.
+    NOT_A_COUNTER,
+    /// The key given to `ADD` is already cached.
+    EXISTS
 }
 
 impl CommandResult {
     pub fn as_bytes(&self) -> Vec<u8> {
         match self {
             &CommandResult::SUCCESS => "success".to_string().as_bytes().to_vec(),
-            &CommandResult::FAILURE => "failure".to_string().as_bytes().to_vec()
+            &CommandResult::NOT_FOUND => "not_found".to_string().as_bytes().to_vec(),
+            &CommandResult::VALUE_TOO_LARGE => "value_too_large".to_string().as_bytes().to_vec(),
+            &CommandResult::BAD_REQUEST => "bad_request".to_string().as_bytes().to_vec(),
+            &CommandResult::EXPIRED => "expired".to_string().as_bytes().to_vec(),
+            &CommandResult::SERVER_ERROR => "server_error".to_string().as_bytes().to_vec(),
+            &CommandResult::READ_ONLY => "read_only".to_string().as_bytes().to_vec(),
+            &CommandResult::VERSION_MISMATCH => "version_mismatch".to_string().as_bytes().to_vec(),
+            &CommandResult::NOT_A_COUNTER => "not_a_counter".to_string().as_bytes().to_vec(),
+            &CommandResult::EXISTS => "exists".to_string().as_bytes().to_vec()
         }
     }
 
@@ -47,21 +122,32 @@ impl CommandResult {
         str::from_utf8(&bytes)
                 .ok()
                 .and_then(|result| {
-                    if result == "success" {
-                        Some(CommandResult::SUCCESS)
-                    } else if result == "failure" {
-                        Some(CommandResult::FAILURE)
-                    } else {
-                        None
+                    match result {
+                        "success" => Some(CommandResult::SUCCESS),
+                        "not_found" => Some(CommandResult::NOT_FOUND),
+                        "value_too_large" => Some(CommandResult::VALUE_TOO_LARGE),
+                        "bad_request" => Some(CommandResult::BAD_REQUEST),
+                        "expired" => Some(CommandResult::EXPIRED),
+                        "server_error" => Some(CommandResult::SERVER_ERROR),
+                        "read_only" => Some(CommandResult::READ_ONLY),
+                        "version_mismatch" => Some(CommandResult::VERSION_MISMATCH),
+                        "not_a_counter" => Some(CommandResult::NOT_A_COUNTER),
+                        "exists" => Some(CommandResult::EXISTS),
+                        _ => None
                     }
                 })
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct CacheCommand {
     pub command: Command,
-    pub key: String,
+    pub key: Vec<u8>,
+    /// Seconds until the entry expires. Sent as `0` on the wire when `None`.
+    pub ttl: Option<u64>,
+    /// The version `CAS` expects the entry to be at. Sent as `0` on the wire
+    /// when `None`.
+    pub version: Option<u64>,
     pub length: u64,
     pub value: Vec<u8>
 }
@@ -69,6 +155,9 @@ pub struct CacheCommand {
 #[derive(Debug)]
 pub struct CacheResponse {
     pub response_type: CommandResult,
+    /// The entry's version after a successful `GET`, or a command that wrote
+    /// it. Sent as `0` on the wire when `None`.
+    pub version: Option<u64>,
     pub length: u64,
     pub data: Vec<u8>
 }
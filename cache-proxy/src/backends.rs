@@ -0,0 +1,370 @@
+use std::collections::{ BTreeMap, HashMap };
+use std::io;
+use std::sync::{ Mutex, RwLock };
+use std::time::{ Duration, Instant };
+use cache_client::{ CacheClient, CacheClientBuilder, ClientError, Ring };
+use cache_codec::batch;
+use cache_codec::types::{ CacheCommand, CacheResponse, Command, CommandResult };
+
+struct Health {
+    failures: u32,
+    ejected_until: Option<Instant>
+}
+
+struct Backend {
+    address: String,
+    client: CacheClient,
+    health: Mutex<Health>
+}
+
+/// The backends taking part in the ring, by index into `Backends::backends`.
+struct Live {
+    ring: Ring,
+    backends: Vec<usize>
+}
+
+/// The cache servers behind the proxy, with keys spread over them by the same
+/// consistent hash the client library uses.
+///
+/// A backend whose connection fails or times out `eject_after` times in a row
+/// is taken out of the ring, and its keys fall to the remaining backends. It
+/// is put back `retry_after` later, on probation: one more failure ejects it
+/// again.
+pub struct Backends {
+    backends: Vec<Backend>,
+    live: RwLock<Live>,
+    eject_after: u32,
+    retry_after: Duration
+}
+
+impl Backends {
+    /// Returns `None` if an address cannot be resolved. An `eject_after` of 0
+    /// keeps every backend in the ring however often it fails.
+    pub fn new(addresses: Vec<String>,
+               timeout: Duration,
+               eject_after: u32,
+               retry_after: Duration) -> Option<Self> {
+        let backends: Option<Vec<Backend>> = addresses.into_iter().map(|address| {
+            CacheClientBuilder::new(address.clone())
+                .connect_timeout(timeout)
+                .request_timeout(timeout)
+                .build()
+                .map(|client| Backend {
+                    address: address,
+                    client: client,
+                    health: Mutex::new(Health { failures: 0, ejected_until: None })
+                })
+        }).collect();
+
+        backends.map(|backends| {
+            let addresses: Vec<String> = backends.iter().map(|backend| backend.address.clone()).collect();
+            Backends {
+                live: RwLock::new(Live { ring: Ring::new(&addresses), backends: (0..backends.len()).collect() }),
+                backends: backends,
+                eject_after: eject_after,
+                retry_after: retry_after
+            }
+        })
+    }
+
+    /// Sends `command` to the backend for its key, to every backend for
+    /// `STATS`, or split between backends for a batch, and returns the
+    /// response to relay. A backend that cannot be reached is answered for
+    /// with `SERVER_ERROR`.
+    pub fn forward(&self, command: CacheCommand) -> CacheResponse {
+        match command.command {
+            Command::STATS => return self.stats(),
+            Command::MGET | Command::MSET => return self.forward_batch(command),
+            _ => {}
+        }
+
+        let index = match self.route(&command.key) {
+            Some(index) => index,
+            None => return respond(CommandResult::SERVER_ERROR, vec![])
+        };
+        match self.call(index, |client| client.call(command)) {
+            Ok(response) => response,
+            Err(_) => respond(CommandResult::SERVER_ERROR, vec![])
+        }
+    }
+
+    /// Sends each backend the part of an `MGET` or `MSET` batch it holds the
+    /// keys for, and puts the results back together in request order. Keys
+    /// whose backend cannot be reached get `SERVER_ERROR`.
+    fn forward_batch(&self, command: CacheCommand) -> CacheResponse {
+        let decoded: io::Result<Vec<(Vec<u8>, Option<Vec<u8>>)>> = match command.command {
+            Command::MGET => batch::decode_keys(&command.value)
+                                 .map(|keys| keys.into_iter().map(|key| (key, None)).collect()),
+            _ => batch::decode_entries(&command.value)
+                     .map(|entries| entries.into_iter().map(|(key, value)| (key, Some(value))).collect())
+        };
+        let entries = match decoded {
+            Ok(entries) => entries,
+            Err(_) => return respond(CommandResult::BAD_REQUEST, vec![])
+        };
+
+        let mut by_backend: HashMap<usize, Vec<usize>> = HashMap::new();
+        for (index, &(ref key, _)) in entries.iter().enumerate() {
+            if let Some(backend) = self.route(key) {
+                by_backend.entry(backend).or_insert_with(Vec::new).push(index);
+            }
+        }
+
+        let mut results: Vec<CacheResponse> = entries.iter().map(|_| respond(CommandResult::SERVER_ERROR, vec![])).collect();
+        for (backend, indices) in by_backend {
+            let value = match command.command {
+                Command::MGET => {
+                    let keys: Vec<Vec<u8>> = indices.iter().map(|&index| entries[index].0.clone()).collect();
+                    batch::encode_keys(&keys)
+                },
+                _ => {
+                    let part: Vec<(Vec<u8>, Vec<u8>)> = indices.iter().map(|&index| {
+                        (entries[index].0.clone(), entries[index].1.clone().unwrap_or_default())
+                    }).collect();
+                    batch::encode_entries(&part)
+                }
+            };
+            let part = CacheCommand {
+                command: command.command.clone(),
+                key: vec![],
+                ttl: command.ttl,
+                version: None,
+                length: value.len() as u64,
+                value: value
+            };
+
+            let response = match self.call(backend, |client| client.call(part)) {
+                Ok(response) => response,
+                Err(_) => continue
+            };
+            if response.response_type != CommandResult::SUCCESS {
+                for &index in indices.iter() {
+                    results[index] = respond(response.response_type.clone(), vec![]);
+                }
+                continue;
+            }
+            if let Ok(parts) = batch::decode_responses(&response.data) {
+                if parts.len() == indices.len() {
+                    for (&index, result) in indices.iter().zip(parts) {
+                        results[index] = result;
+                    }
+                }
+            }
+        }
+
+        respond(CommandResult::SUCCESS, batch::encode_responses(&results))
+    }
+
+    /// Every live backend's counters summed, plus how many backends are live
+    /// and ejected.
+    fn stats(&self) -> CacheResponse {
+        let live = self.live_backends();
+        let mut totals = BTreeMap::new();
+        for &index in live.iter() {
+            if let Ok(stats) = self.call(index, |client| client.stats()) {
+                for (name, value) in stats {
+                    *totals.entry(name).or_insert(0) += value;
+                }
+            }
+        }
+        totals.insert("backends".to_string(), live.len() as u64);
+        totals.insert("backends_ejected".to_string(), (self.backends.len() - live.len()) as u64);
+
+        let lines: String = totals.iter().map(|(name, value)| format!("{} {}\n", name, value)).collect();
+        respond(CommandResult::SUCCESS, lines.into_bytes())
+    }
+
+    fn route(&self, key: &[u8]) -> Option<usize> {
+        self.readmit();
+        let live = self.live.read().unwrap();
+        if live.backends.is_empty() {
+            None
+        } else {
+            Some(live.backends[live.ring.server(key)])
+        }
+    }
+
+    fn live_backends(&self) -> Vec<usize> {
+        self.readmit();
+        self.live.read().unwrap().backends.clone()
+    }
+
+    fn call<T, F>(&self, index: usize, request: F) -> Result<T, ClientError>
+        where F: FnOnce(&CacheClient) -> Result<T, ClientError> {
+        let result = request(&self.backends[index].client);
+        match result {
+            Err(ClientError::Io(_)) | Err(ClientError::Timeout) => self.failed(index),
+            _ => self.backends[index].health.lock().unwrap().failures = 0
+        }
+        result
+    }
+
+    fn failed(&self, index: usize) {
+        let backend = &self.backends[index];
+        {
+            let mut health = backend.health.lock().unwrap();
+            health.failures += 1;
+            if self.eject_after == 0 || health.failures < self.eject_after || health.ejected_until.is_some() {
+                return;
+            }
+            health.ejected_until = Some(Instant::now() + self.retry_after);
+        }
+        eprintln!("ejected backend {}", backend.address);
+        self.rebuild();
+    }
+
+    /// Puts back any ejected backend whose time is up.
+    fn readmit(&self) {
+        if self.live.read().unwrap().backends.len() == self.backends.len() {
+            return;
+        }
+
+        let now = Instant::now();
+        let mut readmitted = false;
+        for backend in self.backends.iter() {
+            let mut health = backend.health.lock().unwrap();
+            if health.ejected_until.map_or(false, |until| until <= now) {
+                health.ejected_until = None;
+                health.failures = self.eject_after.saturating_sub(1);
+                readmitted = true;
+                println!("readmitted backend {}", backend.address);
+            }
+        }
+        if readmitted {
+            self.rebuild();
+        }
+    }
+
+    fn rebuild(&self) {
+        let backends: Vec<usize> = (0..self.backends.len()).filter(|&index| {
+            self.backends[index].health.lock().unwrap().ejected_until.is_none()
+        }).collect();
+        let addresses: Vec<String> = backends.iter().map(|&index| self.backends[index].address.clone()).collect();
+
+        *self.live.write().unwrap() = Live { ring: Ring::new(&addresses), backends: backends };
+    }
+}
+
+fn respond(response_type: CommandResult, data: Vec<u8>) -> CacheResponse {
+    CacheResponse {
+        response_type: response_type,
+        version: None,
+        length: data.iter().len() as u64,
+        data: data
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Duration;
+    use cache_codec::{ batch, counter };
+    use cache_codec::types::{ CacheCommand, Command, CommandResult };
+    use test_backend::TestBackend;
+    use super::Backends;
+
+    fn unreachable_address() -> String {
+        TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string()
+    }
+
+    fn command(command: Command, key: &str, value: &[u8]) -> CacheCommand {
+        CacheCommand {
+            command: command,
+            key: key.to_string().into_bytes(),
+            ttl: None,
+            version: None,
+            length: value.len() as u64,
+            value: value.to_vec()
+        }
+    }
+
+    fn put_all(backends: &Backends) -> Vec<CommandResult> {
+        (0..20).map(|i| {
+            backends.forward(command(Command::PUT, &format!("key{}", i), b"value")).response_type
+        }).collect()
+    }
+
+    #[test]
+    fn test_relays_conditional_writes_and_counts() {
+        let backend = TestBackend::start();
+        let backends = Backends::new(vec![backend.address.to_string()],
+                                     Duration::from_secs(1), 2, Duration::from_secs(30)).unwrap();
+
+        let created = backends.forward(command(Command::INCR, "hits", &counter::encode_request(1, Some(5))));
+        assert_eq!(created.data, counter::encode_value(5));
+        let counted = backends.forward(command(Command::INCR, "hits", &counter::encode_request(2, None)));
+        assert_eq!(counted.data, counter::encode_value(7));
+
+        let mut swap = command(Command::CAS, "hits", b"x");
+        swap.version = counted.version;
+        assert_eq!(backends.forward(swap).response_type, CommandResult::SUCCESS);
+        assert_eq!(backends.forward(command(Command::ADD, "hits", b"y")).response_type, CommandResult::EXISTS);
+        assert_eq!(backends.forward(command(Command::APPEND, "hits", b"z")).response_type, CommandResult::SUCCESS);
+        assert_eq!(backends.forward(command(Command::GET, "hits", b"")).data, b"xz".to_vec());
+    }
+
+    #[test]
+    fn test_forwards_to_backends_and_sums_stats() {
+        let first = TestBackend::start();
+        let second = TestBackend::start();
+        let backends = Backends::new(vec![first.address.to_string(), second.address.to_string()],
+                                     Duration::from_secs(1), 2, Duration::from_secs(30)).unwrap();
+
+        assert!(put_all(&backends).iter().all(|result| *result == CommandResult::SUCCESS));
+        let found = backends.forward(command(Command::GET, "key3", b""));
+        assert_eq!(found.data, b"value".to_vec());
+
+        let stats = String::from_utf8(backends.forward(command(Command::STATS, "", b"")).data).unwrap();
+        assert!(stats.contains("entries 20\n"));
+        assert!(stats.contains("backends 2\n"));
+        assert!(first.entries() > 0 && second.entries() > 0);
+    }
+
+    #[test]
+    fn test_ejects_failing_backend() {
+        let live = TestBackend::start();
+        let backends = Backends::new(vec![live.address.to_string(), unreachable_address()],
+                                     Duration::from_secs(1), 2, Duration::from_secs(30)).unwrap();
+
+        let failed = put_all(&backends).iter().filter(|result| **result == CommandResult::SERVER_ERROR).count();
+        assert_eq!(failed, 2);
+        assert!(put_all(&backends).iter().all(|result| *result == CommandResult::SUCCESS));
+
+        let stats = String::from_utf8(backends.forward(command(Command::STATS, "", b"")).data).unwrap();
+        assert!(stats.contains("backends 1\nbackends_ejected 1\n"));
+    }
+
+    #[test]
+    fn test_readmits_ejected_backend_on_probation() {
+        let backends = Backends::new(vec![unreachable_address(), unreachable_address()],
+                                     Duration::from_secs(1), 1, Duration::from_millis(50)).unwrap();
+        put_all(&backends);
+        assert!(backends.live_backends().is_empty());
+        assert_eq!(backends.forward(command(Command::GET, "key", b"")).response_type, CommandResult::SERVER_ERROR);
+
+        thread::sleep(Duration::from_millis(60));
+        assert_eq!(backends.live_backends().len(), 2);
+        backends.forward(command(Command::GET, "key", b""));
+        assert_eq!(backends.live_backends().len(), 1);
+    }
+
+    #[test]
+    fn test_splits_batches_between_backends() {
+        let first = TestBackend::start();
+        let second = TestBackend::start();
+        let backends = Backends::new(vec![first.address.to_string(), second.address.to_string()],
+                                     Duration::from_secs(1), 2, Duration::from_secs(30)).unwrap();
+
+        let entries: Vec<(Vec<u8>, Vec<u8>)> = (0..10u8).map(|i| (format!("key{}", i).into_bytes(), vec![i])).collect();
+        let stored = backends.forward(command(Command::MSET, "", &batch::encode_entries(&entries)));
+        assert_eq!(batch::decode_responses(&stored.data).unwrap().len(), 10);
+        assert!(first.entries() > 0 && second.entries() > 0);
+
+        let keys = vec![b"key9".to_vec(), b"missing".to_vec(), b"key0".to_vec()];
+        let found = batch::decode_responses(&backends.forward(command(Command::MGET, "", &batch::encode_keys(&keys))).data).unwrap();
+        assert_eq!(found[0].data, vec![9]);
+        assert_eq!(found[1].response_type, CommandResult::NOT_FOUND);
+        assert_eq!(found[2].data, vec![0]);
+    }
+}
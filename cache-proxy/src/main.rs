@@ -0,0 +1,133 @@
+extern crate clap;
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_proto;
+extern crate tokio_service;
+extern crate cache_client;
+extern crate cache_codec;
+#[cfg(test)]
+extern crate cache_server;
+
+mod backends;
+mod proto;
+mod service;
+mod workers;
+#[cfg(test)]
+mod test_backend;
+
+use clap::{ Arg, App, ArgMatches };
+use std::process;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_proto::TcpServer;
+use backends::Backends;
+use proto::CacheCommandProto;
+use service::ProxySrv;
+use workers::Workers;
+
+fn is_number(value: String) -> Result<(), String> {
+    value.parse::<u64>().map(|_| ()).map_err(|_| format!("expected a whole number, found {}", value))
+}
+
+fn is_positive(value: String) -> Result<(), String> {
+    match value.parse::<u64>() {
+        Ok(0) => Err("expected a number greater than 0".to_string()),
+        Ok(_) => Ok(()),
+        Err(_) => Err(format!("expected a whole number, found {}", value))
+    }
+}
+
+/// The value of an argument already checked by `is_number` or `is_positive`.
+fn number<T: FromStr>(matches: &ArgMatches, name: &str) -> Option<T> {
+    matches.value_of(name).and_then(|value| value.parse().ok())
+}
+
+fn main() {
+    let matches = App::new("CacheProxy")
+                      .version("001.0")
+                      .author("Stuart <shterrett@gmail.com>")
+                      .arg(Arg::with_name("address")
+                           .help("ip address to listen on")
+                           .short("a")
+                           .long("address")
+                           .takes_value(true))
+                      .arg(Arg::with_name("port")
+                           .help("port to listen on")
+                           .short("p")
+                           .long("port")
+                           .takes_value(true))
+                      .arg(Arg::with_name("backend")
+                           .help("host:port of a cache server to forward to; give once per server")
+                           .short("b")
+                           .long("backend")
+                           .required(true)
+                           .multiple(true)
+                           .number_of_values(1)
+                           .takes_value(true))
+                      .arg(Arg::with_name("backend-timeout")
+                           .help("milliseconds to wait to connect to, or hear back from, a backend")
+                           .long("backend-timeout")
+                           .default_value("1000")
+                           .validator(is_positive)
+                           .takes_value(true))
+                      .arg(Arg::with_name("eject-after")
+                           .help("consecutive failures after which a backend is ejected; 0 never ejects")
+                           .long("eject-after")
+                           .default_value("2")
+                           .validator(is_number)
+                           .takes_value(true))
+                      .arg(Arg::with_name("retry-ejected")
+                           .help("seconds before an ejected backend is tried again")
+                           .long("retry-ejected")
+                           .default_value("30")
+                           .validator(is_number)
+                           .takes_value(true))
+                      .arg(Arg::with_name("threads")
+                           .help("number of event loop threads accepting connections")
+                           .short("t")
+                           .long("threads")
+                           .default_value("1")
+                           .validator(is_positive)
+                           .takes_value(true))
+                      .arg(Arg::with_name("workers")
+                           .help("number of threads waiting on backends")
+                           .short("w")
+                           .long("workers")
+                           .default_value("16")
+                           .validator(is_positive)
+                           .takes_value(true))
+                      .get_matches();
+
+
+    let mut addr = matches.value_of("address").unwrap_or("0.0.0.0").to_string();
+    let port = matches.value_of("port").unwrap_or("8080");
+    addr.push_str(":");
+    addr.push_str(port);
+    let addresses = matches.values_of("backend").unwrap().map(|backend| backend.to_string()).collect();
+    let timeout = number(&matches, "backend-timeout").unwrap_or(1000);
+    let eject_after = number(&matches, "eject-after").unwrap_or(2);
+    let retry_after = number(&matches, "retry-ejected").unwrap_or(30);
+    let threads = number(&matches, "threads").unwrap_or(1);
+    let workers = number(&matches, "workers").unwrap_or(16);
+
+    let backends = match Backends::new(addresses,
+                                       Duration::from_millis(timeout),
+                                       eject_after,
+                                       Duration::from_secs(retry_after)) {
+        Some(backends) => Arc::new(backends),
+        None => {
+            eprintln!("could not resolve every backend address");
+            process::exit(1);
+        }
+    };
+    let workers = Arc::new(Workers::new(workers));
+
+    let mut server = TcpServer::new(CacheCommandProto, addr.parse().unwrap());
+    server.threads(usize::max(threads, 1));
+    server.serve(move || Ok(
+        ProxySrv {
+            backends: backends.clone(),
+            workers: workers.clone()
+        }));
+}
@@ -0,0 +1,31 @@
+use std::io;
+use std::sync::Arc;
+use futures::{ Future, BoxFuture };
+use tokio_service::Service;
+use cache_codec::types::{ CacheCommand, CacheResponse };
+use backends::Backends;
+use workers::Workers;
+
+/// Relays each command to the backends.
+///
+/// Backend requests block, so they run on shared worker threads rather than
+/// on the event loop; the number of workers bounds how many are in flight at
+/// once.
+pub struct ProxySrv {
+    pub backends: Arc<Backends>,
+    pub workers: Arc<Workers>
+}
+
+impl Service for ProxySrv {
+    type Request = CacheCommand;
+    type Response = CacheResponse;
+    type Error = io::Error;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn call(&self, req: Self::Request) -> Self::Future {
+        let backends = self.backends.clone();
+        self.workers.run(move || backends.forward(req))
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "proxy worker stopped"))
+            .boxed()
+    }
+}
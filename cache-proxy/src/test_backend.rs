@@ -0,0 +1,69 @@
+use std::io::{ Read, Write };
+use std::net::{ SocketAddr, TcpListener, TcpStream };
+use std::sync::Arc;
+use std::thread;
+use futures::Future;
+use tokio_core::io::{ Codec, EasyBuf };
+use tokio_service::Service;
+use cache_codec::server_codec::CacheServerCodec;
+use cache_server::replication::Role;
+use cache_server::service::CacheSrv;
+use cache_server::shards::Shards;
+use cache_server::storage::{ Limits, Policy };
+
+/// An in-process cache server to stand behind the proxy. Commands are run by
+/// the real server's service over an unbounded store.
+pub struct TestBackend {
+    pub address: SocketAddr,
+    service: Arc<CacheSrv>
+}
+
+impl TestBackend {
+    pub fn start() -> TestBackend {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let service = Arc::new(CacheSrv {
+            cache: Arc::new(Shards::new(1, Policy::Lru, Limits::new(None, None))),
+            max_value_size: u64::pow(2, 20),
+            oplog: None,
+            role: Role::Standalone
+        });
+        let shared = service.clone();
+
+        thread::spawn(move || {
+            for socket in listener.incoming() {
+                let socket = socket.unwrap();
+                let service = shared.clone();
+                thread::spawn(move || serve(socket, service));
+            }
+        });
+
+        TestBackend { address: address, service: service }
+    }
+
+    pub fn entries(&self) -> u64 {
+        self.service.cache.stats().unwrap().entries
+    }
+}
+
+fn serve(mut socket: TcpStream, service: Arc<CacheSrv>) {
+    let mut codec = CacheServerCodec::default();
+    let mut buf = EasyBuf::new();
+    let mut chunk = [0; 1024];
+
+    loop {
+        let read = match socket.read(&mut chunk) {
+            Ok(0) | Err(_) => return,
+            Ok(read) => read
+        };
+        buf.get_mut().extend_from_slice(&chunk[..read]);
+
+        let mut out = vec![];
+        while let Some(command) = codec.decode(&mut buf).unwrap() {
+            codec.encode(service.call(command).wait().unwrap(), &mut out).unwrap();
+        }
+        if socket.write_all(&out).is_err() {
+            return;
+        }
+    }
+}
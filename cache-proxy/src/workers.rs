@@ -0,0 +1,47 @@
+use std::sync::{ Arc, Mutex };
+use std::sync::mpsc::{ channel, Sender };
+use std::thread;
+use futures::sync::oneshot;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// A fixed set of threads for running blocking jobs off the event loop.
+pub struct Workers {
+    jobs: Mutex<Sender<Job>>
+}
+
+impl Workers {
+    pub fn new(count: usize) -> Self {
+        let (jobs, queue) = channel::<Job>();
+        let queue = Arc::new(Mutex::new(queue));
+        for _ in 0..count.max(1) {
+            let queue = queue.clone();
+            thread::spawn(move || {
+                loop {
+                    let job = match queue.lock() {
+                        Ok(queue) => queue.recv(),
+                        Err(_) => return
+                    };
+                    match job {
+                        Ok(job) => job(),
+                        Err(_) => return
+                    }
+                }
+            });
+        }
+
+        Workers { jobs: Mutex::new(jobs) }
+    }
+
+    /// Queues `job`, returning a future of its result. The future is canceled
+    /// if the job cannot be run.
+    pub fn run<T, F>(&self, job: F) -> oneshot::Receiver<T>
+        where T: Send + 'static,
+              F: FnOnce() -> T + Send + 'static {
+        let (result, receiver) = oneshot::channel();
+        if let Ok(jobs) = self.jobs.lock() {
+            let _ = jobs.send(Box::new(move || result.complete(job())));
+        }
+        receiver
+    }
+}
@@ -0,0 +1,22 @@
+//! The cache server's storage, persistence and request handling. The
+//! `cache-server` binary wires these together from its command line; other
+//! crates' tests use them to run a real server in process.
+
+extern crate futures;
+extern crate tokio_core;
+extern crate tokio_proto;
+extern crate tokio_service;
+extern crate byteorder;
+extern crate cache_codec;
+#[cfg(unix)]
+extern crate libc;
+
+pub mod oplog;
+pub mod proto;
+pub mod replication;
+pub mod service;
+pub mod shards;
+pub mod shutdown;
+pub mod snapshot;
+pub mod storage;
+pub mod store;
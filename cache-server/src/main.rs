@@ -1,21 +1,40 @@
 extern crate clap;
-extern crate futures;
-extern crate tokio_core;
 extern crate tokio_proto;
-extern crate tokio_service;
-extern crate byteorder;
-extern crate cache_codec;
-extern crate lru_cache;
+extern crate cache_server;
 
-mod proto;
-mod service;
-
-use clap::{ Arg, App };
-use std::sync::{ Arc, Mutex };
+use clap::{ Arg, App, ArgMatches };
+use std::io;
+use std::net::TcpListener;
+use std::process;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
 use tokio_proto::TcpServer;
-use lru_cache::cache::LruCache;
-use service::CacheSrv;
-use proto::CacheCommandProto;
+use cache_server::oplog::{ Fsync, OpLog };
+use cache_server::replication::{ Primary, Replica, Role };
+use cache_server::service::CacheSrv;
+use cache_server::shards::Shards;
+use cache_server::storage::{ Limits, Policy };
+use cache_server::proto::CacheCommandProto;
+use cache_server::{ shutdown, snapshot };
+
+fn is_number(value: String) -> Result<(), String> {
+    value.parse::<u64>().map(|_| ()).map_err(|_| format!("expected a whole number, found {}", value))
+}
+
+fn is_positive(value: String) -> Result<(), String> {
+    match value.parse::<u64>() {
+        Ok(0) => Err("expected a number greater than 0".to_string()),
+        Ok(_) => Ok(()),
+        Err(_) => Err(format!("expected a whole number, found {}", value))
+    }
+}
+
+/// The value of an argument already checked by `is_number` or `is_positive`.
+fn number<T: FromStr>(matches: &ArgMatches, name: &str) -> Option<T> {
+    matches.value_of(name).and_then(|value| value.parse().ok())
+}
 
 fn main() {
     let matches = App::new("CacheServer")
@@ -31,6 +50,87 @@ fn main() {
                            .short("p")
                            .long("port")
                            .takes_value(true))
+                      .arg(Arg::with_name("max-value-size")
+                           .help("largest value in bytes that will be cached")
+                           .long("max-value-size")
+                           .validator(is_number)
+                           .takes_value(true))
+                      .arg(Arg::with_name("max-request-size")
+                           .help("largest key or value in bytes, batches included, that a client may send")
+                           .long("max-request-size")
+                           .default_value("67108864")
+                           .validator(is_positive)
+                           .takes_value(true))
+                      .arg(Arg::with_name("capacity")
+                           .help("most entries to cache; defaults to 512 unless --max-memory is set")
+                           .short("c")
+                           .long("capacity")
+                           .validator(is_positive)
+                           .takes_value(true))
+                      .arg(Arg::with_name("max-memory")
+                           .help("most bytes of memory to use for keys, values and per-entry bookkeeping; \
+                                  a single entry must fit in one shard's share")
+                           .short("m")
+                           .long("max-memory")
+                           .validator(is_positive)
+                           .takes_value(true))
+                      .arg(Arg::with_name("eviction-policy")
+                           .help("how to choose entries to evict once the cache is full")
+                           .short("e")
+                           .long("eviction-policy")
+                           .possible_values(Policy::names())
+                           .default_value("lru")
+                           .takes_value(true))
+                      .arg(Arg::with_name("threads")
+                           .help("number of event loop threads accepting connections")
+                           .short("t")
+                           .long("threads")
+                           .default_value("1")
+                           .validator(is_positive)
+                           .takes_value(true))
+                      .arg(Arg::with_name("snapshot-path")
+                           .help("file to restore the cache from at startup and save it to")
+                           .long("snapshot-path")
+                           .takes_value(true))
+                      .arg(Arg::with_name("snapshot-interval")
+                           .help("seconds between snapshots; 0 saves only on shutdown")
+                           .long("snapshot-interval")
+                           .default_value("300")
+                           .validator(is_number)
+                           .takes_value(true))
+                      .arg(Arg::with_name("oplog-path")
+                           .help("file to log puts and deletes to between snapshots")
+                           .long("oplog-path")
+                           .requires("snapshot-path")
+                           .takes_value(true))
+                      .arg(Arg::with_name("fsync")
+                           .help("when logged operations are forced to disk")
+                           .long("fsync")
+                           .possible_values(Fsync::names())
+                           .default_value("everysec")
+                           .takes_value(true))
+                      .arg(Arg::with_name("oplog-compact-size")
+                           .help("size in bytes at which the operation log is compacted into a snapshot")
+                           .long("oplog-compact-size")
+                           .default_value("67108864")
+                           .validator(is_number)
+                           .takes_value(true))
+                      .arg(Arg::with_name("replication-port")
+                           .help("port to accept replicas on, making this server a primary")
+                           .long("replication-port")
+                           .takes_value(true))
+                      .arg(Arg::with_name("replica-of")
+                           .help("host:port of a primary's replication port to copy; only reads are answered")
+                           .long("replica-of")
+                           .conflicts_with_all(&["replication-port", "oplog-path"])
+                           .takes_value(true))
+                      .arg(Arg::with_name("shards")
+                           .help("number of independently locked partitions of the cache")
+                           .short("s")
+                           .long("shards")
+                           .default_value("16")
+                           .validator(is_positive)
+                           .takes_value(true))
                       .get_matches();
 
 
@@ -38,12 +138,85 @@ fn main() {
     let port = matches.value_of("port").unwrap_or("8080");
     addr.push_str(":");
     addr.push_str(port);
+    let max_value_size = number(&matches, "max-value-size").unwrap_or(u64::pow(2, 20));
+    let max_request_size = number(&matches, "max-request-size").unwrap_or(u64::pow(2, 26)).max(max_value_size);
+    let max_memory = number(&matches, "max-memory");
+    let capacity = match number(&matches, "capacity") {
+        Some(capacity) => Some(capacity),
+        None if max_memory.is_some() => None,
+        None => Some(u64::pow(2, 9))
+    };
+    let policy = matches.value_of("eviction-policy").and_then(Policy::from_name).unwrap_or(Policy::Lru);
+    let shards = number(&matches, "shards").unwrap_or(16);
+    let threads = number(&matches, "threads").unwrap_or(1);
+    let snapshot_path = matches.value_of("snapshot-path").map(PathBuf::from);
+    let snapshot_interval = number(&matches, "snapshot-interval")
+                                   .and_then(|secs| if secs > 0 { Some(Duration::from_secs(secs)) } else { None });
+    let oplog_path = matches.value_of("oplog-path").map(PathBuf::from);
+    let fsync = matches.value_of("fsync").and_then(Fsync::from_name).unwrap_or(Fsync::EverySecond);
+    let compact_size = number(&matches, "oplog-compact-size").unwrap_or(u64::pow(2, 26));
+
+    let replication_addr = matches.value_of("replication-port").map(|port| {
+        format!("{}:{}", matches.value_of("address").unwrap_or("0.0.0.0"), port)
+    });
+    let replica_of = matches.value_of("replica-of").map(|primary| primary.to_string());
+
+    let mut server = TcpServer::new(CacheCommandProto { max_frame_length: max_request_size }, addr.parse().unwrap());
+    server.threads(usize::max(threads, 1));
+    let cache = Arc::new(Shards::new(shards, policy, Limits::new(capacity, max_memory)));
+    if let Some(max_entry) = cache.max_entry_bytes() {
+        if max_entry <= max_value_size {
+            eprintln!("values near --max-value-size will not fit in a shard's {} byte share of --max-memory; \
+                       lower --shards or --max-value-size", max_entry);
+        }
+    }
+
+    if let Some(ref path) = snapshot_path {
+        match snapshot::load(&cache, path) {
+            Ok(count) => println!("restored {} entries from {}", count, path.display()),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {},
+            Err(e) => eprintln!("could not restore snapshot from {}: {}", path.display(), e)
+        }
+    }
+
+    let oplog = oplog_path.map(|path| {
+        let replayed = OpLog::open(path.clone(), fsync, compact_size).and_then(|log| {
+            log.replay(&cache).map(|count| (log, count))
+        });
+        match replayed {
+            Ok((log, count)) => {
+                println!("replayed {} operations from {}", count, path.display());
+                Arc::new(log)
+            },
+            Err(e) => {
+                eprintln!("could not replay operation log {}: {}", path.display(), e);
+                process::exit(1);
+            }
+        }
+    });
+
+    let role = match (replication_addr, replica_of) {
+        (Some(addr), _) => match TcpListener::bind(&*addr) {
+            Ok(listener) => Role::Primary(Primary::listen(cache.clone(), listener)),
+            Err(e) => {
+                eprintln!("could not listen for replicas on {}: {}", addr, e);
+                process::exit(1);
+            }
+        },
+        (None, Some(primary)) => Role::Replica(Replica::follow(cache.clone(), primary)),
+        (None, None) => Role::Standalone
+    };
 
-    let server = TcpServer::new(CacheCommandProto, addr.parse().unwrap());
-    let cache = Arc::new(Mutex::new(LruCache::new(u64::pow(2, 9))));
+    if let Some(path) = snapshot_path {
+        shutdown::install();
+        snapshot::schedule(cache.clone(), path, snapshot_interval, oplog.clone());
+    }
 
     server.serve(move || Ok(
         CacheSrv {
-            cache: cache.clone()
+            cache: cache.clone(),
+            max_value_size: max_value_size,
+            oplog: oplog.clone(),
+            role: role.clone()
         }));
 }
@@ -0,0 +1,341 @@
+use std::fs::{ self, File, OpenOptions };
+use std::io::{ self, BufReader, ErrorKind, Read, Write };
+use std::path::{ Path, PathBuf };
+use std::sync::Mutex;
+use byteorder::{ BigEndian, ReadBytesExt, WriteBytesExt };
+use shards::Shards;
+use snapshot::{ self, read_bytes, write_bytes };
+
+// A log starts with these 8 bytes and a u32 format version, followed by one
+// record per operation: a 1 byte tag, the key as a u64 length and the bytes,
+// and for a put the stored entry, header included, the same way.
+// Records are written whole, so only the last one can be cut short by a
+// crash; replay drops it.
+const MAGIC: &'static [u8] = b"cLRUdlog";
+const VERSION: u32 = 3;
+const PUT: u8 = 1;
+const DELETE: u8 = 2;
+
+/// When appended operations are forced to disk.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Fsync {
+    /// Before each operation is acknowledged.
+    Always,
+    /// About once a second, so a crash loses at most a second of operations.
+    EverySecond,
+    /// Whenever the operating system gets round to it.
+    Never
+}
+
+impl Fsync {
+    pub fn names() -> &'static [&'static str] {
+        &["always", "everysec", "never"]
+    }
+
+    pub fn from_name(name: &str) -> Option<Fsync> {
+        match name {
+            "always" => Some(Fsync::Always),
+            "everysec" => Some(Fsync::EverySecond),
+            "never" => Some(Fsync::Never),
+            _ => None
+        }
+    }
+}
+
+/// An append-only log of the puts and deletes applied to the cache since the
+/// last snapshot.
+///
+/// Operations are appended before they are applied, while the key's shard is
+/// locked, so the log holds each key's operations in the order they took
+/// effect. Replaying the log over the snapshot brings the cache back to where
+/// it was; entries that have expired in the meantime are skipped.
+///
+/// Compaction moves the log aside, starts a fresh one and saves a snapshot.
+/// Once the snapshot is written the old log is no longer needed. Should the
+/// server stop before then, both logs are replayed at the next start.
+pub struct OpLog {
+    path: PathBuf,
+    file: Mutex<File>,
+    fsync: Fsync,
+    compact_size: u64
+}
+
+impl OpLog {
+    /// Opens the log at `path` for appending, creating it if need be. Once it
+    /// grows past `compact_size` bytes, `wants_compaction` says so.
+    pub fn open(path: PathBuf, fsync: Fsync, compact_size: u64) -> io::Result<Self> {
+        let file = open_for_append(&path)?;
+        Ok(OpLog {
+            path: path,
+            file: Mutex::new(file),
+            fsync: fsync,
+            compact_size: compact_size
+        })
+    }
+
+    pub fn fsync(&self) -> Fsync {
+        self.fsync
+    }
+
+    pub fn put(&self, key: &[u8], entry: &[u8]) -> io::Result<()> {
+        let mut record = vec![PUT];
+        write_bytes(&mut record, key)?;
+        write_bytes(&mut record, entry)?;
+        self.append(&record)
+    }
+
+    pub fn delete(&self, key: &[u8]) -> io::Result<()> {
+        let mut record = vec![DELETE];
+        write_bytes(&mut record, key)?;
+        self.append(&record)
+    }
+
+    fn append(&self, record: &[u8]) -> io::Result<()> {
+        let mut file = self.file.lock().map_err(|_| poisoned())?;
+        file.write_all(record)?;
+        if self.fsync == Fsync::Always {
+            file.sync_data()?;
+        }
+        Ok(())
+    }
+
+    /// Forces everything appended so far to disk.
+    pub fn sync(&self) -> io::Result<()> {
+        self.file.lock().map_err(|_| poisoned())?.sync_data()
+    }
+
+    pub fn wants_compaction(&self) -> bool {
+        self.file.lock().ok()
+            .and_then(|file| file.metadata().ok())
+            .map_or(false, |metadata| metadata.len() >= self.compact_size)
+    }
+
+    /// Applies every operation in the log, and in one left over from an
+    /// unfinished compaction, to `shards`. Returns how many were read.
+    ///
+    /// A record cut short at the end of the log is dropped and the log
+    /// truncated after the last whole one, so later appends can be read back.
+    pub fn replay(&self, shards: &Shards) -> io::Result<u64> {
+        let mut replayed = 0;
+        if let Some((count, _)) = replay_file(shards, &self.rotated())? {
+            replayed += count;
+        }
+        if let Some((count, length)) = replay_file(shards, &self.path)? {
+            self.file.lock().map_err(|_| poisoned())?.set_len(length)?;
+            replayed += count;
+        }
+        Ok(replayed)
+    }
+
+    /// Starts a new log and saves a snapshot to `snapshot_path` covering
+    /// everything in the old one, then removes the old log. Returns how many
+    /// entries the snapshot holds.
+    pub fn compact(&self, shards: &Shards, snapshot_path: &Path) -> io::Result<u64> {
+        self.rotate()?;
+        let saved = snapshot::save(shards, snapshot_path)?;
+        match fs::remove_file(self.rotated()) {
+            Ok(()) => Ok(saved),
+            Err(ref e) if e.kind() == ErrorKind::NotFound => Ok(saved),
+            Err(e) => Err(e)
+        }
+    }
+
+    /// Moves the log aside and starts a new one. If an earlier compaction
+    /// failed and left its log behind, that log is not yet covered by a
+    /// snapshot, so the current one is kept and appended to instead.
+    fn rotate(&self) -> io::Result<()> {
+        let mut file = self.file.lock().map_err(|_| poisoned())?;
+        let rotated = self.rotated();
+        if rotated.exists() {
+            return Ok(());
+        }
+
+        file.sync_all()?;
+        fs::rename(&self.path, &rotated)?;
+        *file = open_for_append(&self.path)?;
+        Ok(())
+    }
+
+    fn rotated(&self) -> PathBuf {
+        self.path.with_extension("old")
+    }
+}
+
+fn open_for_append(path: &Path) -> io::Result<File> {
+    let mut file = OpenOptions::new().read(true).append(true).create(true).open(path)?;
+    if file.metadata()?.len() == 0 {
+        file.write_all(MAGIC)?;
+        file.write_u32::<BigEndian>(VERSION)?;
+        file.sync_all()?;
+    }
+    Ok(file)
+}
+
+/// Replays the log at `path`, if there is one, returning how many operations
+/// it held and the length of the log up to the end of the last whole record.
+fn replay_file(shards: &Shards, path: &Path) -> io::Result<Option<(u64, u64)>> {
+    let mut input = match File::open(path) {
+        Ok(file) => BufReader::new(file),
+        Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e)
+    };
+
+    let mut magic = vec![0; MAGIC.len()];
+    input.read_exact(&mut magic)?;
+    if magic != MAGIC {
+        return Err(invalid(format!("{} is not an operation log", path.display())));
+    }
+    let version = input.read_u32::<BigEndian>()?;
+    if version != VERSION {
+        return Err(invalid(format!("unsupported operation log version {}", version)));
+    }
+
+    let mut length = (MAGIC.len() + 4) as u64;
+    let mut replayed = 0;
+    loop {
+        let tag = match input.read_u8() {
+            Ok(tag) => tag,
+            Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(Some((replayed, length))),
+            Err(e) => return Err(e)
+        };
+        let record = match tag {
+            PUT => read_bytes(&mut input).and_then(|key| {
+                read_bytes(&mut input).map(|entry| (key, Some(entry)))
+            }),
+            DELETE => read_bytes(&mut input).map(|key| (key, None)),
+            tag => return Err(invalid(format!("unknown operation tag {}", tag)))
+        };
+        let (key, entry) = match record {
+            Ok(record) => record,
+            Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(Some((replayed, length))),
+            Err(e) => return Err(e)
+        };
+
+        length += 1 + 8 + key.len() as u64 + entry.as_ref().map_or(0, |entry| 8 + entry.len() as u64);
+        let mut store = shards.shard(&key).lock().map_err(|_| poisoned())?;
+        match entry {
+            Some(entry) => { store.load(key, entry); },
+            None => { store.remove(&key); }
+        }
+        replayed += 1;
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+fn poisoned() -> io::Error {
+    io::Error::new(ErrorKind::Other, "operation log lock poisoned")
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs::{ self, OpenOptions };
+    use std::path::PathBuf;
+    use std::process;
+    use std::time::Duration;
+    use shards::Shards;
+    use snapshot;
+    use storage::{ Limits, Policy };
+    use store::{ entry, Lookup };
+    use super::{ Fsync, OpLog };
+
+    fn temp_path(name: &str, extension: &str) -> PathBuf {
+        env::temp_dir().join(format!("clrud-{}-{}.{}", process::id(), name, extension))
+    }
+
+    fn shards() -> Shards {
+        Shards::new(2, Policy::Lru, Limits::new(None, None))
+    }
+
+    fn get(shards: &Shards, key: &[u8]) -> Lookup {
+        shards.shard(key).lock().unwrap().get(&key.to_vec())
+    }
+
+    #[test]
+    fn test_replays_puts_and_deletes_in_order() {
+        let path = temp_path("replay", "log");
+        let log = OpLog::open(path.clone(), Fsync::Always, 1 << 20).unwrap();
+        log.put(b"a", &entry(vec![1], None, 1)).unwrap();
+        log.put(b"b", &entry(vec![2], None, 1)).unwrap();
+        log.put(b"a", &entry(vec![3], None, 1)).unwrap();
+        log.delete(b"b").unwrap();
+        log.put(b"gone", &entry(vec![4], Some(Duration::from_secs(0)), 1)).unwrap();
+
+        let restored = shards();
+        let replayed = OpLog::open(path.clone(), Fsync::Always, 1 << 20).unwrap().replay(&restored).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(replayed, 5);
+        assert_eq!(get(&restored, b"a"), Lookup::Hit(vec![3]));
+        assert_eq!(get(&restored, b"b"), Lookup::Miss);
+        assert_eq!(get(&restored, b"gone"), Lookup::Miss);
+    }
+
+    #[test]
+    fn test_drops_torn_record_and_keeps_appending() {
+        let path = temp_path("torn", "log");
+        let log = OpLog::open(path.clone(), Fsync::Never, 1 << 20).unwrap();
+        log.put(b"a", &entry(vec![1], None, 1)).unwrap();
+        log.put(b"b", &entry(vec![2], None, 1)).unwrap();
+        OpenOptions::new().write(true).open(&path).unwrap().set_len(fs::metadata(&path).unwrap().len() - 1).unwrap();
+
+        let log = OpLog::open(path.clone(), Fsync::Never, 1 << 20).unwrap();
+        assert_eq!(log.replay(&shards()).unwrap(), 1);
+        log.put(b"c", &entry(vec![3], None, 1)).unwrap();
+
+        let restored = shards();
+        let replayed = OpLog::open(path.clone(), Fsync::Never, 1 << 20).unwrap().replay(&restored).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(replayed, 2);
+        assert_eq!(get(&restored, b"b"), Lookup::Miss);
+        assert_eq!(get(&restored, b"c"), Lookup::Hit(vec![3]));
+    }
+
+    #[test]
+    fn test_compaction_moves_log_into_snapshot() {
+        let path = temp_path("compact", "log");
+        let snapshot_path = temp_path("compact", "snapshot");
+        let cache = shards();
+        let log = OpLog::open(path.clone(), Fsync::Never, 64).unwrap();
+        for i in 0..10u8 {
+            log.put(&[i], &entry(vec![i], None, 1)).unwrap();
+            cache.shard(&[i]).lock().unwrap().put(vec![i], vec![i], None);
+        }
+        assert!(log.wants_compaction());
+
+        assert_eq!(log.compact(&cache, &snapshot_path).unwrap(), 10);
+        assert!(!log.wants_compaction());
+        log.delete(&[3]).unwrap();
+
+        let restored = shards();
+        snapshot::load(&restored, &snapshot_path).unwrap();
+        let replayed = log.replay(&restored).unwrap();
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&snapshot_path).unwrap();
+
+        assert_eq!(replayed, 1);
+        assert_eq!(get(&restored, &[3]), Lookup::Miss);
+        assert_eq!(get(&restored, &[7]), Lookup::Hit(vec![7]));
+    }
+
+    #[test]
+    fn test_replays_log_left_by_unfinished_compaction() {
+        let path = temp_path("unfinished", "log");
+        let log = OpLog::open(path.clone(), Fsync::Never, 1 << 20).unwrap();
+        log.put(b"a", &entry(vec![1], None, 1)).unwrap();
+        log.rotate().unwrap();
+        log.put(b"b", &entry(vec![2], None, 1)).unwrap();
+
+        let restored = shards();
+        assert_eq!(log.replay(&restored).unwrap(), 2);
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(log.rotated()).unwrap();
+
+        assert_eq!(get(&restored, b"a"), Lookup::Hit(vec![1]));
+        assert_eq!(get(&restored, b"b"), Lookup::Hit(vec![2]));
+    }
+}
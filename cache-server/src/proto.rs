@@ -4,7 +4,10 @@ use tokio_proto::pipeline::ServerProto;
 use cache_codec::types::{ CacheCommand, CacheResponse };
 use cache_codec::server_codec::CacheServerCodec;
 
-pub struct CacheCommandProto;
+pub struct CacheCommandProto {
+    /// Longest key or value, batches included, that a client may send.
+    pub max_frame_length: u64
+}
 
 impl<T: Io + 'static> ServerProto<T> for CacheCommandProto {
     type Request = CacheCommand;
@@ -13,6 +16,6 @@ impl<T: Io + 'static> ServerProto<T> for CacheCommandProto {
     type BindTransport = Result<Self::Transport, io::Error>;
 
     fn bind_transport(&self, io: T) -> Self::BindTransport {
-        Ok(io.framed(CacheServerCodec {}))
+        Ok(io.framed(CacheServerCodec::new(self.max_frame_length)))
     }
 }
@@ -0,0 +1,288 @@
+use std::io::{ self, BufReader, BufWriter, ErrorKind, Write };
+use std::net::{ TcpListener, TcpStream };
+use std::sync::{ Arc, Mutex };
+use std::sync::atomic::{ AtomicBool, AtomicU64, Ordering };
+use std::sync::mpsc::{ sync_channel, Receiver, RecvTimeoutError, SyncSender };
+use std::thread;
+use std::time::Duration;
+use byteorder::{ BigEndian, ReadBytesExt, WriteBytesExt };
+use shards::Shards;
+use snapshot::{ self, read_bytes, write_bytes };
+use store;
+
+// A replica is sent a snapshot, in the same format as on disk, followed by a
+// stream of messages. Each message is the primary's clock in milliseconds
+// since the epoch when it was sent, a 1 byte tag, and for a put or delete the
+// key and, for a put, the stored entry, each as a u64 length and the bytes.
+// Heartbeats carry only the clock, so that an idle replica can still tell
+// how far behind it is.
+const PUT: u8 = 1;
+const DELETE: u8 = 2;
+const HEARTBEAT: u8 = 3;
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+// Messages a replica may fall behind by before the primary drops it. It
+// reconnects and starts over from a fresh snapshot.
+const BACKLOG: usize = 1 << 16;
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What part a server plays in replication.
+#[derive(Clone)]
+pub enum Role {
+    Standalone,
+    /// Sends every write on to its replicas.
+    Primary(Arc<Primary>),
+    /// Copies a primary and only answers reads.
+    Replica(Arc<Replica>)
+}
+
+impl Role {
+    /// Replication stats as `name value` lines, in the form of `Stats::encode`.
+    pub fn encode_stats(&self) -> Vec<u8> {
+        match *self {
+            Role::Standalone => vec![],
+            Role::Primary(ref primary) => format!("replicas {}\n", primary.replicas()).into_bytes(),
+            Role::Replica(ref replica) => {
+                format!("replication_connected {}\nreplication_lag_ms {}\n",
+                        replica.connected() as u8, replica.lag_ms()).into_bytes()
+            }
+        }
+    }
+}
+
+/// The sending side of replication.
+///
+/// Writes are passed on while the key's shard is locked, so each replica
+/// receives a key's writes in the order they were applied.
+pub struct Primary {
+    replicas: Mutex<Vec<SyncSender<Vec<u8>>>>
+}
+
+impl Primary {
+    /// Accepts replicas on `listener`, sending each a snapshot of `shards`
+    /// and then every write passed to `put` and `delete`.
+    pub fn listen(shards: Arc<Shards>, listener: TcpListener) -> Arc<Primary> {
+        let primary = Arc::new(Primary { replicas: Mutex::new(vec![]) });
+        let accepting = primary.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => accepting.serve(shards.clone(), stream),
+                    Err(e) => eprintln!("could not accept replica: {}", e)
+                }
+            }
+        });
+        primary
+    }
+
+    pub fn put(&self, key: &[u8], entry: &[u8]) {
+        let mut message = header(PUT);
+        write_bytes(&mut message, key).unwrap();
+        write_bytes(&mut message, entry).unwrap();
+        self.broadcast(message);
+    }
+
+    pub fn delete(&self, key: &[u8]) {
+        let mut message = header(DELETE);
+        write_bytes(&mut message, key).unwrap();
+        self.broadcast(message);
+    }
+
+    pub fn replicas(&self) -> usize {
+        self.replicas.lock().map(|replicas| replicas.len()).unwrap_or(0)
+    }
+
+    /// Queues `message` for every replica, dropping any that have fallen too
+    /// far behind or disconnected.
+    fn broadcast(&self, message: Vec<u8>) {
+        if let Ok(mut replicas) = self.replicas.lock() {
+            replicas.retain(|replica| replica.try_send(message.clone()).is_ok());
+        }
+    }
+
+    /// The replica is registered before the snapshot is taken, so a write
+    /// either lands in the snapshot, is queued for afterwards, or both.
+    /// Applying it twice leaves the same result.
+    fn serve(&self, shards: Arc<Shards>, stream: TcpStream) {
+        let (sender, receiver) = sync_channel(BACKLOG);
+        match self.replicas.lock() {
+            Ok(mut replicas) => replicas.push(sender),
+            Err(_) => return
+        }
+
+        thread::spawn(move || {
+            let peer = stream.peer_addr().map(|addr| addr.to_string()).unwrap_or_default();
+            println!("replica {} connected", peer);
+            if let Err(e) = feed(&shards, stream, receiver) {
+                eprintln!("replica {} disconnected: {}", peer, e);
+            }
+        });
+    }
+}
+
+fn feed(shards: &Shards, stream: TcpStream, receiver: Receiver<Vec<u8>>) -> io::Result<()> {
+    let mut out = BufWriter::new(stream);
+    snapshot::write_to(shards, &mut out)?;
+    out.flush()?;
+
+    loop {
+        match receiver.recv_timeout(HEARTBEAT_INTERVAL) {
+            Ok(message) => out.write_all(&message)?,
+            Err(RecvTimeoutError::Timeout) => out.write_all(&header(HEARTBEAT))?,
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(io::Error::new(ErrorKind::Other, "fell too far behind"));
+            }
+        }
+        while let Ok(message) = receiver.try_recv() {
+            out.write_all(&message)?;
+        }
+        out.flush()?;
+    }
+}
+
+fn header(tag: u8) -> Vec<u8> {
+    let mut message = vec![];
+    message.write_u64::<BigEndian>(store::now()).unwrap();
+    message.push(tag);
+    message
+}
+
+/// The receiving side of replication.
+///
+/// Lag is how long ago, by the primary's clock, the last message applied was
+/// sent, so it counts on while the primary is unreachable. It assumes the two
+/// hosts' clocks roughly agree.
+pub struct Replica {
+    primary: String,
+    connected: AtomicBool,
+    last_sent: AtomicU64
+}
+
+impl Replica {
+    /// Copies the primary listening for replicas at `primary` into `shards`,
+    /// reconnecting and starting over whenever the connection drops.
+    pub fn follow(shards: Arc<Shards>, primary: String) -> Arc<Replica> {
+        let replica = Arc::new(Replica {
+            primary: primary,
+            connected: AtomicBool::new(false),
+            last_sent: AtomicU64::new(store::now())
+        });
+        let following = replica.clone();
+        thread::spawn(move || {
+            loop {
+                if let Err(e) = following.sync(&shards) {
+                    eprintln!("lost primary {}: {}", following.primary, e);
+                }
+                following.connected.store(false, Ordering::SeqCst);
+                thread::sleep(RECONNECT_INTERVAL);
+            }
+        });
+        replica
+    }
+
+    pub fn connected(&self) -> bool {
+        self.connected.load(Ordering::SeqCst)
+    }
+
+    pub fn lag_ms(&self) -> u64 {
+        store::now().saturating_sub(self.last_sent.load(Ordering::SeqCst))
+    }
+
+    fn sync(&self, shards: &Shards) -> io::Result<()> {
+        let stream = TcpStream::connect(&*self.primary)?;
+        stream.set_read_timeout(Some(HEARTBEAT_INTERVAL * 5))?;
+        let mut input = BufReader::new(stream);
+
+        for shard in shards.stores() {
+            shard.lock().map_err(|_| poisoned())?.clear();
+        }
+        let loaded = snapshot::read_from(shards, &mut input)?;
+        println!("synced {} entries from primary {}", loaded, self.primary);
+        self.connected.store(true, Ordering::SeqCst);
+
+        loop {
+            let sent = input.read_u64::<BigEndian>()?;
+            match input.read_u8()? {
+                PUT => {
+                    let key = read_bytes(&mut input)?;
+                    let entry = read_bytes(&mut input)?;
+                    shards.shard(&key).lock().map_err(|_| poisoned())?.load(key, entry);
+                },
+                DELETE => {
+                    let key = read_bytes(&mut input)?;
+                    shards.shard(&key).lock().map_err(|_| poisoned())?.remove(&key);
+                },
+                HEARTBEAT => {},
+                tag => return Err(io::Error::new(ErrorKind::InvalidData, format!("unknown message tag {}", tag)))
+            }
+            self.last_sent.store(sent, Ordering::SeqCst);
+        }
+    }
+}
+
+fn poisoned() -> io::Error {
+    io::Error::new(ErrorKind::Other, "cache lock poisoned")
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::TcpListener;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{ Duration, Instant };
+    use shards::Shards;
+    use storage::{ Limits, Policy };
+    use store::{ entry, Lookup };
+    use super::{ Primary, Replica };
+
+    fn shards() -> Arc<Shards> {
+        Arc::new(Shards::new(2, Policy::Lru, Limits::new(None, None)))
+    }
+
+    fn get(shards: &Shards, key: &[u8]) -> Lookup {
+        shards.shard(key).lock().unwrap().get(&key.to_vec())
+    }
+
+    fn eventually<F: Fn() -> bool>(condition: F) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !condition() {
+            assert!(Instant::now() < deadline, "condition not met in time");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_replica_copies_snapshot_then_writes() {
+        let primary_shards = shards();
+        primary_shards.shard(b"old").lock().unwrap().put(b"old".to_vec(), vec![1], None);
+        primary_shards.shard(b"gone").lock().unwrap().put(b"gone".to_vec(), vec![2], None);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let primary = Primary::listen(primary_shards.clone(), listener);
+
+        let replica_shards = shards();
+        let replica = Replica::follow(replica_shards.clone(), address);
+        eventually(|| replica.connected() && primary.replicas() == 1);
+        assert_eq!(get(&replica_shards, b"old"), Lookup::Hit(vec![1]));
+
+        primary.put(b"new", &entry(vec![3], None, 1));
+        primary.delete(b"gone");
+        eventually(|| get(&replica_shards, b"new") == Lookup::Hit(vec![3]));
+
+        assert_eq!(get(&replica_shards, b"gone"), Lookup::Miss);
+        assert!(replica.lag_ms() < 1000);
+    }
+
+    #[test]
+    fn test_replica_reports_growing_lag_without_primary() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        drop(listener);
+
+        let replica = Replica::follow(shards(), address);
+        thread::sleep(Duration::from_millis(50));
+
+        assert!(!replica.connected());
+        assert!(replica.lag_ms() >= 50);
+    }
+}
@@ -1,12 +1,20 @@
 use std::io;
-use std::sync::{ Arc, Mutex };
+use std::sync::{ Arc, MutexGuard };
+use std::time::Duration;
 use tokio_service::Service;
 use futures::{ future, Future, BoxFuture };
+use cache_codec::{ batch, counter };
 use cache_codec::types::{ Command, CommandResult, CacheCommand, CacheResponse };
-use lru_cache::cache::LruCache;
+use oplog::OpLog;
+use replication::Role;
+use shards::Shards;
+use store::{ self, Lookup, Store };
 
 pub struct CacheSrv {
-    pub cache: Arc<Mutex<LruCache<String>>>
+    pub cache: Arc<Shards>,
+    pub max_value_size: u64,
+    pub oplog: Option<Arc<OpLog>>,
+    pub role: Role
 }
 
 impl Service for CacheSrv {
@@ -16,60 +24,319 @@ impl Service for CacheSrv {
     type Future = BoxFuture<Self::Response, Self::Error>;
 
     fn call(&self, req: Self::Request) -> Self::Future {
-        match req.command {
-            Command::PUT => {
-                self.cache.lock().unwrap().put(req.key, req.value);
-                let response = CacheResponse {
-                    response_type: CommandResult::SUCCESS,
-                    length: 0,
-                    data: vec![]
-                };
-                future::ok(response).boxed()
-            },
-            Command::GET => {
-                match self.cache.lock().unwrap().get(&req.key) {
-                    Some(data) => {
-                        let response = CacheResponse {
-                            response_type: CommandResult::SUCCESS,
-                            length: data.iter().len() as u64,
-                            data: data.clone()
-                        };
-                        future::ok(response).boxed()
+        if req.length != req.value.iter().len() as u64 {
+            return future::ok(response(CommandResult::BAD_REQUEST, vec![])).boxed();
+        }
+        if let Some(ttl) = req.ttl {
+            if store::expires_at(Duration::from_secs(ttl)).is_none() {
+                return future::ok(response(CommandResult::BAD_REQUEST, vec![])).boxed();
+            }
+        }
+
+        if writes(&req.command) {
+            if let Role::Replica(_) = self.role {
+                return future::ok(response(CommandResult::READ_ONLY, vec![])).boxed();
+            }
+        }
+
+        let response = match req.command {
+            Command::PUT => self.put(req.key, req.value, req.ttl, Condition::Always),
+            Command::ADD => self.put(req.key, req.value, req.ttl, Condition::Absent),
+            Command::REPLACE => self.put(req.key, req.value, req.ttl, Condition::Present),
+            Command::GET => self.get(&req.key),
+            Command::DEL => self.delete(&req.key),
+            Command::STATS => self.stats(),
+            Command::MGET => {
+                match batch::decode_keys(&req.value) {
+                    Ok(keys) => {
+                        let responses: Vec<CacheResponse> = keys.iter().map(|key| self.get(key)).collect();
+                        response(CommandResult::SUCCESS, batch::encode_responses(&responses))
+                    },
+                    Err(_) => response(CommandResult::BAD_REQUEST, vec![])
+                }
+            },
+            Command::MSET => {
+                match batch::decode_entries(&req.value) {
+                    Ok(entries) => {
+                        let ttl = req.ttl;
+                        let responses: Vec<CacheResponse> = entries.into_iter()
+                            .map(|(key, value)| self.put(key, value, ttl, Condition::Always))
+                            .collect();
+                        response(CommandResult::SUCCESS, batch::encode_responses(&responses))
                     },
-                    None => {
-                        let msg = "Not Found".to_string().as_bytes().to_vec();
-                        let response = CacheResponse {
-                            response_type: CommandResult::FAILURE,
-                            length: msg.iter().len() as u64,
-                            data: msg
-                        };
-                        future::ok(response).boxed()
-                    }
+                    Err(_) => response(CommandResult::BAD_REQUEST, vec![])
+                }
+            },
+            Command::CAS => {
+                match req.version {
+                    Some(version) => self.put(req.key, req.value, req.ttl, Condition::Version(version)),
+                    None => response(CommandResult::BAD_REQUEST, vec![])
+                }
+            },
+            Command::APPEND => self.extend(req.key, req.value, false),
+            Command::PREPEND => self.extend(req.key, req.value, true),
+            Command::INCR => self.count(req.key, &req.value, req.ttl, false),
+            Command::DECR => self.count(req.key, &req.value, req.ttl, true)
+        };
+        future::ok(response).boxed()
+    }
+}
+
+impl CacheSrv {
+    /// Stores the value, if the entry under `key` meets `condition`.
+    fn put(&self, key: Vec<u8>, value: Vec<u8>, ttl: Option<u64>, condition: Condition) -> CacheResponse {
+        if value.len() as u64 > self.max_value_size {
+            return response(CommandResult::VALUE_TOO_LARGE, vec![]);
+        }
+        let mut cache = match self.shard(&key) {
+            Ok(cache) => cache,
+            Err(failed) => return failed
+        };
+
+        let current = match condition {
+            Condition::Always => None,
+            _ => match cache.version(&key) {
+                Lookup::Hit(version) => Some(version),
+                Lookup::Expired | Lookup::Miss => None
+            }
+        };
+        match (condition, current) {
+            (Condition::Always, _) | (Condition::Absent, None) | (Condition::Present, Some(_)) => {},
+            (Condition::Version(expected), Some(version)) if version == expected => {},
+            (Condition::Version(_), Some(_)) => return response(CommandResult::VERSION_MISMATCH, vec![]),
+            (Condition::Absent, Some(_)) => return response(CommandResult::EXISTS, vec![]),
+            (Condition::Present, None) | (Condition::Version(_), None) => {
+                return response(CommandResult::NOT_FOUND, vec![])
+            }
+        }
+        let entry = cache.entry(value, ttl.map(Duration::from_secs));
+        match self.write(&mut cache, key, entry) {
+            Ok(version) => versioned(response(CommandResult::SUCCESS, vec![]), version),
+            Err(failed) => failed
+        }
+    }
+
+    /// Adds to the counter under `key`, or subtracts from it when
+    /// `decrement`, by the amount in `request`; see `counter`. Only entries
+    /// made by `INCR` or `DECR` are counters, and counts saturate at 0 and
+    /// `u64::MAX` rather than wrap.
+    fn count(&self, key: Vec<u8>, request: &[u8], ttl: Option<u64>, decrement: bool) -> CacheResponse {
+        let (delta, initial) = match counter::decode_request(request) {
+            Ok(request) => request,
+            Err(_) => return response(CommandResult::BAD_REQUEST, vec![])
+        };
+        let mut cache = match self.shard(&key) {
+            Ok(cache) => cache,
+            Err(failed) => return failed
+        };
+
+        let (count, entry) = match (cache.get_entry(&key), initial) {
+            (Lookup::Hit(entry), _) => {
+                let count = match store::count(&entry) {
+                    Some(count) if decrement => count.saturating_sub(delta),
+                    Some(count) => count.saturating_add(delta),
+                    None => return response(CommandResult::NOT_A_COUNTER, vec![])
+                };
+                (count, cache.recount(&entry, count))
+            },
+            (_, Some(initial)) => (initial, cache.counter(initial, ttl.map(Duration::from_secs))),
+            (Lookup::Expired, None) => return response(CommandResult::EXPIRED, vec![]),
+            (Lookup::Miss, None) => return response(CommandResult::NOT_FOUND, vec![])
+        };
+        match self.write(&mut cache, key, entry) {
+            Ok(version) => versioned(response(CommandResult::SUCCESS, counter::encode_value(count)), version),
+            Err(failed) => failed
+        }
+    }
+
+    /// Adds `data` to the end of the value under `key`, or to the start when
+    /// `prepend`, keeping the entry's expiry.
+    fn extend(&self, key: Vec<u8>, data: Vec<u8>, prepend: bool) -> CacheResponse {
+        let mut cache = match self.shard(&key) {
+            Ok(cache) => cache,
+            Err(failed) => return failed
+        };
+
+        let entry = match cache.get_entry(&key) {
+            Lookup::Hit(entry) => entry,
+            Lookup::Expired => return response(CommandResult::EXPIRED, vec![]),
+            Lookup::Miss => return response(CommandResult::NOT_FOUND, vec![])
+        };
+        let current = store::value(&entry);
+        if (current.len() + data.len()) as u64 > self.max_value_size {
+            return response(CommandResult::VALUE_TOO_LARGE, vec![]);
+        }
+        let value = if prepend {
+            [&data[..], current].concat()
+        } else {
+            [current, &data[..]].concat()
+        };
+
+        let revised = cache.revise(&entry, value);
+        match self.write(&mut cache, key, revised) {
+            Ok(version) => versioned(response(CommandResult::SUCCESS, vec![]), version),
+            Err(failed) => failed
+        }
+    }
+
+    /// Logs and replicates `entry` before storing it under `key` in `cache`,
+    /// the shard holding it. Gives the entry's version, or the response to
+    /// send if it could not be stored.
+    fn write(&self, cache: &mut Store, key: Vec<u8>, entry: Vec<u8>) -> Result<u64, CacheResponse> {
+        if !cache.admits(&key, &entry) {
+            return Err(response(CommandResult::VALUE_TOO_LARGE, vec![]));
+        }
+        let version = store::version(&entry);
+        if let Some(ref log) = self.oplog {
+            if log.put(&key, &entry).is_err() {
+                return Err(response(CommandResult::SERVER_ERROR, vec![]));
+            }
+        }
+        let stored = match self.role {
+            Role::Primary(ref primary) => {
+                let stored = cache.put_entry(key.clone(), entry.clone());
+                if stored {
+                    primary.put(&key, &entry);
                 }
+                stored
+            },
+            _ => cache.put_entry(key, entry)
+        };
+        if stored {
+            Ok(version)
+        } else {
+            Err(response(CommandResult::VALUE_TOO_LARGE, vec![]))
+        }
+    }
+
+    fn get(&self, key: &Vec<u8>) -> CacheResponse {
+        let mut cache = match self.shard(key) {
+            Ok(cache) => cache,
+            Err(failed) => return failed
+        };
+
+        match cache.get_versioned(key) {
+            Lookup::Hit((data, version)) => versioned(response(CommandResult::SUCCESS, data), version),
+            Lookup::Expired => response(CommandResult::EXPIRED, vec![]),
+            Lookup::Miss => response(CommandResult::NOT_FOUND, vec![])
+        }
+    }
+
+    fn delete(&self, key: &Vec<u8>) -> CacheResponse {
+        let mut cache = match self.shard(key) {
+            Ok(cache) => cache,
+            Err(failed) => return failed
+        };
+
+        // Only a key that was there needs its delete logged and replicated.
+        let result = match cache.version(key) {
+            Lookup::Hit(_) => CommandResult::SUCCESS,
+            Lookup::Expired => CommandResult::EXPIRED,
+            Lookup::Miss => return response(CommandResult::NOT_FOUND, vec![])
+        };
+        if let Some(ref log) = self.oplog {
+            if log.delete(key).is_err() {
+                return response(CommandResult::SERVER_ERROR, vec![]);
             }
         }
+        if let Role::Primary(ref primary) = self.role {
+            primary.delete(key);
+        }
+        cache.remove(key);
+        response(result, vec![])
+    }
+
+    fn stats(&self) -> CacheResponse {
+        match self.cache.stats() {
+            Some(stats) => {
+                let mut lines = stats.encode();
+                lines.extend(self.role.encode_stats());
+                response(CommandResult::SUCCESS, lines)
+            },
+            None => response(CommandResult::SERVER_ERROR, vec![])
+        }
     }
+
+    /// Locks the shard holding `key`, or gives the response to send if its
+    /// lock has been poisoned.
+    fn shard(&self, key: &[u8]) -> Result<MutexGuard<'_, Store>, CacheResponse> {
+        self.cache.shard(key).lock().map_err(|_| response(CommandResult::SERVER_ERROR, vec![]))
+    }
+}
+
+/// What the entry under a key must be like for a put to go ahead.
+#[derive(Clone, Copy)]
+enum Condition {
+    Always,
+    /// The key is not cached.
+    Absent,
+    /// The key is cached.
+    Present,
+    /// The entry is at this version.
+    Version(u64)
+}
+
+/// Whether `command` changes the cache, and so is refused by a replica.
+fn writes(command: &Command) -> bool {
+    match *command {
+        Command::PUT | Command::DEL | Command::MSET | Command::CAS |
+        Command::INCR | Command::DECR | Command::ADD | Command::REPLACE |
+        Command::APPEND | Command::PREPEND => true,
+        Command::GET | Command::STATS | Command::MGET => false
+    }
+}
+
+fn response(response_type: CommandResult, data: Vec<u8>) -> CacheResponse {
+    CacheResponse {
+        response_type: response_type,
+        version: None,
+        length: data.iter().len() as u64,
+        data: data
+    }
+}
+
+fn versioned(response: CacheResponse, version: u64) -> CacheResponse {
+    CacheResponse { version: Some(version), ..response }
 }
 
 #[cfg(test)]
 mod test {
-    use std::sync::{ Arc, Mutex };
+    use std::env;
+    use std::fs;
+    use std::net::TcpListener;
+    use std::process;
+    use std::sync::Arc;
     use futures::Future;
     use tokio_service::Service;
-    use lru_cache::cache::LruCache;
+    use std::thread;
+    use std::time::Duration;
+    use oplog::{ Fsync, OpLog };
+    use replication::{ Primary, Replica, Role };
+    use shards::Shards;
+    use storage::{ Limits, Policy };
+    use store::Lookup;
+    use cache_codec::{ batch, counter };
     use cache_codec::types::{ Command, CommandResult, CacheCommand };
     use super::{ CacheSrv };
 
+    fn shards() -> Arc<Shards> {
+        Arc::new(Shards::new(4, Policy::Lru, Limits::new(Some(32), None)))
+    }
+
     #[test]
     fn test_puts_in_cache() {
         let value = "message".to_string().as_bytes().to_vec();
-        let cache = LruCache::new(8);
         let service = CacheSrv {
-            cache: Arc::new(Mutex::new(cache))
+            cache: shards(),
+            max_value_size: 1024,
+            oplog: None,
+            role: Role::Standalone
         };
         let request = CacheCommand {
             command: Command::PUT,
-            key: "key".to_string(),
+            key: "key".to_string().into_bytes(),
+            ttl: None,
+            version: None,
             value: value.clone(),
             length: value.iter().len() as u64
         };
@@ -79,8 +346,8 @@ mod test {
                 assert_eq!(response.response_type, CommandResult::SUCCESS);
                 assert_eq!(response.length, 0);
                 assert_eq!(response.data, vec![]);
-                assert_eq!(service.cache.lock().unwrap().get(&"key".to_string()),
-                           Some(&value)
+                assert_eq!(service.cache.shard(b"key").lock().unwrap().get(&"key".to_string().into_bytes()),
+                           Lookup::Hit(value)
                           );
             },
             Err(e) => {
@@ -91,15 +358,17 @@ mod test {
 
     #[test]
     fn test_gets_from_cache() {
-        let key = "key".to_string();
+        let key = "key".to_string().into_bytes();
         let value = "message".to_string().as_bytes().to_vec();
-        let cache = Arc::new(Mutex::new(LruCache::new(8)));
-        let service = CacheSrv { cache: cache.clone() };
-        service.cache.lock().unwrap().put(key.clone(), value.clone());
+        let cache = shards();
+        let service = CacheSrv { cache: cache.clone(), max_value_size: 1024, oplog: None, role: Role::Standalone };
+        service.cache.shard(&key).lock().unwrap().put(key.clone(), value.clone(), None);
 
         let request = CacheCommand {
             command: Command::GET,
             key: key.clone(),
+            ttl: None,
+            version: None,
             value: vec![],
             length: 0
         };
@@ -118,26 +387,450 @@ mod test {
 
     #[test]
     fn test_get_not_present() {
-        let cache = Arc::new(Mutex::new(LruCache::new(8)));
-        let service = CacheSrv { cache: cache.clone() };
+        let cache = shards();
+        let service = CacheSrv { cache: cache.clone(), max_value_size: 1024, oplog: None, role: Role::Standalone };
 
         let request = CacheCommand {
             command: Command::GET,
-            key: "key".to_string(),
+            key: "key".to_string().into_bytes(),
+            ttl: None,
+            version: None,
+            value: vec![],
+            length: 0
+        };
+
+        match service.call(request).wait() {
+            Ok(response) => {
+                assert_eq!(response.response_type, CommandResult::NOT_FOUND);
+                assert_eq!(response.length, 0);
+                assert_eq!(response.data, vec![]);
+            },
+            Err(e) => {
+                panic!(e);
+            }
+        }
+    }
+
+    #[test]
+    fn test_deletes_from_cache() {
+        let key = "key".to_string().into_bytes();
+        let value = "message".to_string().as_bytes().to_vec();
+        let cache = shards();
+        let service = CacheSrv { cache: cache.clone(), max_value_size: 1024, oplog: None, role: Role::Standalone };
+        service.cache.shard(&key).lock().unwrap().put(key.clone(), value.clone(), None);
+
+        let request = CacheCommand {
+            command: Command::DEL,
+            key: key.clone(),
+            ttl: None,
+            version: None,
             value: vec![],
             length: 0
         };
 
-        let msg = "Not Found".to_string().as_bytes().to_vec();
         match service.call(request).wait() {
             Ok(response) => {
-                assert_eq!(response.response_type, CommandResult::FAILURE);
-                assert_eq!(response.length, msg.iter().len() as u64);
-                assert_eq!(response.data, msg);
+                assert_eq!(response.response_type, CommandResult::SUCCESS);
+                assert_eq!(response.length, 0);
+                assert_eq!(response.data, vec![]);
+                assert_eq!(service.cache.shard(&key).lock().unwrap().get(&key), Lookup::Miss);
+            },
+            Err(e) => {
+                panic!(e);
+            }
+        }
+    }
+
+    #[test]
+    fn test_delete_not_present() {
+        let cache = shards();
+        let service = CacheSrv { cache: cache.clone(), max_value_size: 1024, oplog: None, role: Role::Standalone };
+
+        let request = CacheCommand {
+            command: Command::DEL,
+            key: "key".to_string().into_bytes(),
+            ttl: None,
+            version: None,
+            value: vec![],
+            length: 0
+        };
+
+        match service.call(request).wait() {
+            Ok(response) => {
+                assert_eq!(response.response_type, CommandResult::NOT_FOUND);
+                assert_eq!(response.length, 0);
+                assert_eq!(response.data, vec![]);
+            },
+            Err(e) => {
+                panic!(e);
+            }
+        }
+    }
+
+    #[test]
+    fn test_get_expired() {
+        let key = "key".to_string().into_bytes();
+        let cache = shards();
+        let service = CacheSrv { cache: cache.clone(), max_value_size: 1024, oplog: None, role: Role::Standalone };
+        service.cache.shard(&key).lock().unwrap().put(key.clone(), vec![1], Some(Duration::from_millis(10)));
+        thread::sleep(Duration::from_millis(20));
+
+        let request = CacheCommand {
+            command: Command::GET,
+            key: key.clone(),
+            ttl: None,
+            version: None,
+            value: vec![],
+            length: 0
+        };
+
+        match service.call(request).wait() {
+            Ok(response) => {
+                assert_eq!(response.response_type, CommandResult::EXPIRED);
+                assert_eq!(response.length, 0);
+                assert_eq!(response.data, vec![]);
             },
             Err(e) => {
                 panic!(e);
             }
         }
     }
+
+    #[test]
+    fn test_put_value_too_large() {
+        let value = "message".to_string().as_bytes().to_vec();
+        let cache = shards();
+        let service = CacheSrv { cache: cache.clone(), max_value_size: 4, oplog: None, role: Role::Standalone };
+
+        let request = CacheCommand {
+            command: Command::PUT,
+            key: "key".to_string().into_bytes(),
+            ttl: None,
+            version: None,
+            value: value.clone(),
+            length: value.iter().len() as u64
+        };
+
+        match service.call(request).wait() {
+            Ok(response) => {
+                assert_eq!(response.response_type, CommandResult::VALUE_TOO_LARGE);
+                assert_eq!(service.cache.shard(b"key").lock().unwrap().get(&"key".to_string().into_bytes()),
+                           Lookup::Miss
+                          );
+            },
+            Err(e) => {
+                panic!(e);
+            }
+        }
+    }
+
+    #[test]
+    fn test_length_mismatch_is_bad_request() {
+        let cache = shards();
+        let service = CacheSrv { cache: cache.clone(), max_value_size: 1024, oplog: None, role: Role::Standalone };
+
+        let request = CacheCommand {
+            command: Command::PUT,
+            key: "key".to_string().into_bytes(),
+            ttl: None,
+            version: None,
+            value: vec![1, 2, 3],
+            length: 2
+        };
+
+        match service.call(request).wait() {
+            Ok(response) => {
+                assert_eq!(response.response_type, CommandResult::BAD_REQUEST);
+            },
+            Err(e) => {
+                panic!(e);
+            }
+        }
+    }
+
+    #[test]
+    fn test_out_of_range_ttl_is_bad_request() {
+        let service = CacheSrv { cache: shards(), max_value_size: 1024, oplog: None, role: Role::Standalone };
+        let mut request = command(Command::PUT, b"key", &[1], None);
+        request.ttl = Some(u64::MAX);
+        assert_eq!(service.call(request).wait().unwrap().response_type, CommandResult::BAD_REQUEST);
+
+        let mut request = command(Command::INCR, b"key", &counter::encode_request(1, Some(0)), None);
+        request.ttl = Some(u64::MAX);
+        assert_eq!(service.call(request).wait().unwrap().response_type, CommandResult::BAD_REQUEST);
+
+        let stored = service.call(command(Command::PUT, b"key", &[1], None)).wait().unwrap();
+        assert_eq!(stored.response_type, CommandResult::SUCCESS);
+    }
+
+    #[test]
+    fn test_stats_report_usage() {
+        let cache = shards();
+        cache.shard(b"key").lock().unwrap().put("key".to_string().into_bytes(), vec![1, 2, 3], None);
+        let service = CacheSrv { cache: cache.clone(), max_value_size: 1024, oplog: None, role: Role::Standalone };
+
+        let request = CacheCommand {
+            command: Command::STATS,
+            key: vec![],
+            ttl: None,
+            version: None,
+            value: vec![],
+            length: 0
+        };
+
+        match service.call(request).wait() {
+            Ok(response) => {
+                assert_eq!(response.response_type, CommandResult::SUCCESS);
+                let stats = String::from_utf8(response.data).unwrap();
+                assert!(stats.contains("entries 1\n"));
+                assert!(stats.contains("max_entries 32\n"));
+            },
+            Err(e) => {
+                panic!(e);
+            }
+        }
+    }
+
+    #[test]
+    fn test_logs_puts_and_deletes() {
+        let path = env::temp_dir().join(format!("clrud-{}-service.log", process::id()));
+        let log = Arc::new(OpLog::open(path.clone(), Fsync::Always, 1 << 20).unwrap());
+        let service = CacheSrv { cache: shards(), max_value_size: 1024, oplog: Some(log), role: Role::Standalone };
+
+        for (command, key, value) in vec![(Command::PUT, "kept", vec![1]),
+                                          (Command::PUT, "deleted", vec![2]),
+                                          (Command::DEL, "deleted", vec![])] {
+            let request = CacheCommand {
+                command: command,
+                key: key.to_string().into_bytes(),
+                ttl: None,
+                version: None,
+                length: value.len() as u64,
+                value: value
+            };
+            assert_eq!(service.call(request).wait().unwrap().response_type, CommandResult::SUCCESS);
+        }
+
+        let restored = shards();
+        let replayed = OpLog::open(path.clone(), Fsync::Always, 1 << 20).unwrap().replay(&restored).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(replayed, 3);
+        assert_eq!(restored.shard(b"kept").lock().unwrap().get(&b"kept".to_vec()), Lookup::Hit(vec![1]));
+        assert_eq!(restored.shard(b"deleted").lock().unwrap().get(&b"deleted".to_vec()), Lookup::Miss);
+    }
+
+    #[test]
+    fn test_does_not_log_refused_writes() {
+        let path = env::temp_dir().join(format!("clrud-{}-refused.log", process::id()));
+        let log = Arc::new(OpLog::open(path.clone(), Fsync::Always, 1 << 20).unwrap());
+        let service = CacheSrv {
+            cache: Arc::new(Shards::new(1, Policy::Lru, Limits::new(None, Some(256)))),
+            max_value_size: 1024,
+            oplog: Some(log),
+            role: Role::Standalone
+        };
+
+        let refused = service.call(command(Command::PUT, b"key", &[0; 512], None)).wait().unwrap();
+        assert_eq!(refused.response_type, CommandResult::VALUE_TOO_LARGE);
+        let missing = service.call(command(Command::DEL, b"missing", &[], None)).wait().unwrap();
+        assert_eq!(missing.response_type, CommandResult::NOT_FOUND);
+
+        let replayed = OpLog::open(path.clone(), Fsync::Always, 1 << 20).unwrap().replay(&shards()).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(replayed, 0);
+    }
+
+    #[test]
+    fn test_replicates_only_stored_writes() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let primary_shards = Arc::new(Shards::new(1, Policy::Lru, Limits::new(None, Some(256))));
+        let primary = Primary::listen(primary_shards.clone(), listener);
+        let service = CacheSrv {
+            cache: primary_shards,
+            max_value_size: 1024,
+            oplog: None,
+            role: Role::Primary(primary.clone())
+        };
+        let replica_shards = shards();
+        let replica = Replica::follow(replica_shards.clone(), address);
+        while !(replica.connected() && primary.replicas() == 1) {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let refused = service.call(command(Command::PUT, b"big", &[0; 512], None)).wait().unwrap();
+        assert_eq!(refused.response_type, CommandResult::VALUE_TOO_LARGE);
+        service.call(command(Command::PUT, b"small", &[1], None)).wait().unwrap();
+        while replica_shards.shard(b"small").lock().unwrap().get(&b"small".to_vec()) == Lookup::Miss {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        assert_eq!(replica_shards.shard(b"big").lock().unwrap().get(&b"big".to_vec()), Lookup::Miss);
+    }
+
+    #[test]
+    fn test_replica_rejects_writes() {
+        let unused = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+        let replica = Replica::follow(shards(), unused);
+        let service = CacheSrv { cache: shards(), max_value_size: 1024, oplog: None, role: Role::Replica(replica) };
+
+        let request = CacheCommand {
+            command: Command::PUT,
+            key: "key".to_string().into_bytes(),
+            ttl: None,
+            version: None,
+            value: vec![1],
+            length: 1
+        };
+
+        match service.call(request).wait() {
+            Ok(response) => {
+                assert_eq!(response.response_type, CommandResult::READ_ONLY);
+                assert_eq!(service.cache.shard(b"key").lock().unwrap().get(&b"key".to_vec()), Lookup::Miss);
+            },
+            Err(e) => {
+                panic!(e);
+            }
+        }
+    }
+
+    #[test]
+    fn test_mset_then_mget() {
+        let service = CacheSrv { cache: shards(), max_value_size: 4, oplog: None, role: Role::Standalone };
+        let entries = batch::encode_entries(&[(b"a".to_vec(), vec![1]), (b"big".to_vec(), vec![0; 5])]);
+        let request = CacheCommand {
+            command: Command::MSET,
+            key: vec![],
+            ttl: None,
+            version: None,
+            length: entries.len() as u64,
+            value: entries
+        };
+
+        let stored = batch::decode_responses(&service.call(request).wait().unwrap().data).unwrap();
+        assert_eq!(stored.iter().map(|response| &response.response_type).collect::<Vec<_>>(),
+                   vec![&CommandResult::SUCCESS, &CommandResult::VALUE_TOO_LARGE]);
+
+        let keys = batch::encode_keys(&[b"a".to_vec(), b"big".to_vec(), b"a".to_vec()]);
+        let request = CacheCommand {
+            command: Command::MGET,
+            key: vec![],
+            ttl: None,
+            version: None,
+            length: keys.len() as u64,
+            value: keys
+        };
+
+        match service.call(request).wait() {
+            Ok(response) => {
+                assert_eq!(response.response_type, CommandResult::SUCCESS);
+                let found = batch::decode_responses(&response.data).unwrap();
+                assert_eq!(found.len(), 3);
+                assert_eq!(found[0].data, vec![1]);
+                assert_eq!(found[1].response_type, CommandResult::NOT_FOUND);
+                assert_eq!(found[2].data, vec![1]);
+            },
+            Err(e) => {
+                panic!(e);
+            }
+        }
+    }
+
+    #[test]
+    fn test_malformed_batch_is_bad_request() {
+        let service = CacheSrv { cache: shards(), max_value_size: 1024, oplog: None, role: Role::Standalone };
+        let request = CacheCommand {
+            command: Command::MGET,
+            key: vec![],
+            ttl: None,
+            version: None,
+            length: 3,
+            value: vec![0, 0, 9]
+        };
+
+        assert_eq!(service.call(request).wait().unwrap().response_type, CommandResult::BAD_REQUEST);
+    }
+
+    fn command(command: Command, key: &[u8], value: &[u8], version: Option<u64>) -> CacheCommand {
+        CacheCommand {
+            command: command,
+            key: key.to_vec(),
+            ttl: None,
+            version: version,
+            length: value.len() as u64,
+            value: value.to_vec()
+        }
+    }
+
+    #[test]
+    fn test_cas_needs_current_version() {
+        let service = CacheSrv { cache: shards(), max_value_size: 1024, oplog: None, role: Role::Standalone };
+        let put = service.call(command(Command::PUT, b"key", &[1], None)).wait().unwrap();
+        let got = service.call(command(Command::GET, b"key", &[], None)).wait().unwrap();
+        assert_eq!(got.version, put.version);
+        let version = put.version.unwrap();
+
+        let swapped = service.call(command(Command::CAS, b"key", &[2], Some(version))).wait().unwrap();
+        assert_eq!(swapped.response_type, CommandResult::SUCCESS);
+        assert!(swapped.version.unwrap() > version);
+
+        let stale = service.call(command(Command::CAS, b"key", &[3], Some(version))).wait().unwrap();
+        assert_eq!(stale.response_type, CommandResult::VERSION_MISMATCH);
+        assert_eq!(service.call(command(Command::GET, b"key", &[], None)).wait().unwrap().data, vec![2]);
+
+        let missing = service.call(command(Command::CAS, b"other", &[1], Some(version))).wait().unwrap();
+        assert_eq!(missing.response_type, CommandResult::NOT_FOUND);
+        let unversioned = service.call(command(Command::CAS, b"key", &[1], None)).wait().unwrap();
+        assert_eq!(unversioned.response_type, CommandResult::BAD_REQUEST);
+    }
+
+    #[test]
+    fn test_counts_up_and_down() {
+        let service = CacheSrv { cache: shards(), max_value_size: 1024, oplog: None, role: Role::Standalone };
+        let count = |command_type, request: Vec<u8>| {
+            service.call(command(command_type, b"hits", &request, None)).wait().unwrap()
+        };
+
+        assert_eq!(count(Command::INCR, counter::encode_request(1, None)).response_type, CommandResult::NOT_FOUND);
+        assert_eq!(count(Command::INCR, counter::encode_request(1, Some(10))).data, counter::encode_value(10));
+        assert_eq!(count(Command::INCR, counter::encode_request(5, Some(10))).data, counter::encode_value(15));
+        assert_eq!(count(Command::DECR, counter::encode_request(20, None)).data, counter::encode_value(0));
+        assert_eq!(count(Command::INCR, vec![1]).response_type, CommandResult::BAD_REQUEST);
+
+        assert_eq!(count(Command::INCR, counter::encode_request(u64::MAX, None)).data, counter::encode_value(u64::MAX));
+
+        service.call(command(Command::PUT, b"hits", b"text", None)).wait().unwrap();
+        assert_eq!(count(Command::INCR, counter::encode_request(1, None)).response_type, CommandResult::NOT_A_COUNTER);
+        service.call(command(Command::PUT, b"hits", b"abcdefgh", None)).wait().unwrap();
+        assert_eq!(count(Command::INCR, counter::encode_request(1, None)).response_type, CommandResult::NOT_A_COUNTER);
+        assert_eq!(service.call(command(Command::GET, b"hits", &[], None)).wait().unwrap().data, b"abcdefgh".to_vec());
+    }
+
+    #[test]
+    fn test_add_and_replace_check_presence() {
+        let service = CacheSrv { cache: shards(), max_value_size: 1024, oplog: None, role: Role::Standalone };
+        let call = |command_type, value: &[u8]| service.call(command(command_type, b"key", value, None)).wait().unwrap();
+
+        assert_eq!(call(Command::REPLACE, &[1]).response_type, CommandResult::NOT_FOUND);
+        assert_eq!(call(Command::ADD, &[1]).response_type, CommandResult::SUCCESS);
+        assert_eq!(call(Command::ADD, &[2]).response_type, CommandResult::EXISTS);
+        assert_eq!(call(Command::GET, &[]).data, vec![1]);
+        assert_eq!(call(Command::REPLACE, &[3]).response_type, CommandResult::SUCCESS);
+        assert_eq!(call(Command::GET, &[]).data, vec![3]);
+    }
+
+    #[test]
+    fn test_appends_and_prepends_within_max_size() {
+        let service = CacheSrv { cache: shards(), max_value_size: 4, oplog: None, role: Role::Standalone };
+        let call = |command_type, value: &[u8]| service.call(command(command_type, b"key", value, None)).wait().unwrap();
+
+        assert_eq!(call(Command::APPEND, b"b").response_type, CommandResult::NOT_FOUND);
+        call(Command::PUT, b"b");
+        assert_eq!(call(Command::APPEND, b"c").response_type, CommandResult::SUCCESS);
+        assert_eq!(call(Command::PREPEND, b"a").response_type, CommandResult::SUCCESS);
+        assert_eq!(call(Command::GET, &[]).data, b"abc".to_vec());
+        assert_eq!(call(Command::APPEND, b"de").response_type, CommandResult::VALUE_TOO_LARGE);
+        assert_eq!(call(Command::GET, &[]).data, b"abc".to_vec());
+    }
 }
@@ -0,0 +1,131 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{ Hash, Hasher };
+use std::sync::Mutex;
+use storage::{ Limits, Policy };
+use store::{ Stats, Store };
+
+/// The keyspace split by hash across independently locked stores, so that
+/// requests for keys in different shards do not wait on each other.
+///
+/// Each shard gets an even share of the limits, which add up to the limits
+/// given, and evicts on its own. A shard can therefore evict while others
+/// still have room; with many more keys than shards the difference is small.
+/// Likewise an entry must fit in one shard's share of the byte limit, so the
+/// largest entry that can be stored is `max_entry_bytes`.
+pub struct Shards {
+    shards: Vec<Mutex<Store>>
+}
+
+impl Shards {
+    /// Splits `limits` between `count` shards, or fewer if there would not be
+    /// room for an entry in each.
+    pub fn new(count: usize, policy: Policy, limits: Limits) -> Self {
+        let count = limits.max_entries.map_or(count, |max| count.min(max as usize)).max(1);
+        let share = |limit: Option<u64>, shard: usize| limit.map(|limit| {
+            let count = count as u64;
+            limit / count + if (shard as u64) < limit % count { 1 } else { 0 }
+        });
+
+        Shards {
+            shards: (0..count).map(|shard| {
+                let limits = Limits::new(share(limits.max_entries, shard), share(limits.max_bytes, shard));
+                Mutex::new(Store::with_policy(policy, limits))
+            }).collect()
+        }
+    }
+
+    /// The most memory a single entry can take up, counted as the byte limit
+    /// counts it: the smallest shard's share of the limit.
+    pub fn max_entry_bytes(&self) -> Option<u64> {
+        self.shards.iter()
+            .filter_map(|shard| shard.lock().ok().and_then(|store| store.stats().max_bytes))
+            .min()
+    }
+
+    /// The store responsible for `key`.
+    pub fn shard(&self, key: &[u8]) -> &Mutex<Store> {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        &self.shards[hasher.finish() as usize % self.shards.len()]
+    }
+
+    /// Every shard, for walking the whole cache.
+    pub fn stores(&self) -> &[Mutex<Store>] {
+        &self.shards
+    }
+
+    /// Stats summed over every shard, or `None` if a shard's lock has been
+    /// poisoned.
+    pub fn stats(&self) -> Option<Stats> {
+        let mut total = Stats {
+            entries: 0,
+            bytes: 0,
+            evictions: 0,
+            max_entries: Some(0),
+            max_bytes: Some(0)
+        };
+
+        for shard in self.shards.iter() {
+            let stats = match shard.lock() {
+                Ok(store) => store.stats(),
+                Err(_) => return None
+            };
+            total.entries += stats.entries;
+            total.bytes += stats.bytes;
+            total.evictions += stats.evictions;
+            total.max_entries = total.max_entries.and_then(|max| stats.max_entries.map(|shard| max + shard));
+            total.max_bytes = total.max_bytes.and_then(|max| stats.max_bytes.map(|shard| max + shard));
+        }
+        Some(total)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use storage::{ Limits, Policy };
+    use store::Lookup;
+    use super::Shards;
+
+    #[test]
+    fn test_spreads_keys_across_shards() {
+        let shards = Shards::new(4, Policy::Lru, Limits::new(Some(400), None));
+        for i in 0..100u8 {
+            shards.shard(&[i]).lock().unwrap().put(vec![i], vec![i], None);
+        }
+
+        for shard in shards.shards.iter() {
+            assert!(shard.lock().unwrap().stats().entries > 0);
+        }
+        assert_eq!(shards.shard(&[7]).lock().unwrap().get(&vec![7]), Lookup::Hit(vec![7]));
+    }
+
+    #[test]
+    fn test_sums_stats_and_splits_limits() {
+        let shards = Shards::new(3, Policy::Lru, Limits::new(Some(10), None));
+        shards.shard(b"key").lock().unwrap().put(b"key".to_vec(), vec![1], None);
+        let stats = shards.stats().unwrap();
+
+        assert_eq!(stats.entries, 1);
+        assert_eq!(stats.max_entries, Some(10));
+        assert_eq!(stats.max_bytes, None);
+    }
+
+    #[test]
+    fn test_byte_limit_bounds_entries_by_smallest_share() {
+        let shards = Shards::new(3, Policy::Lru, Limits::new(None, Some(3001)));
+
+        assert_eq!(shards.stats().unwrap().max_bytes, Some(3001));
+        assert_eq!(shards.max_entry_bytes(), Some(1000));
+    }
+
+    #[test]
+    fn test_no_more_shards_than_entries() {
+        let shards = Shards::new(16, Policy::Lru, Limits::new(Some(4), None));
+        for i in 0..4u8 {
+            shards.shard(&[i]).lock().unwrap().put(vec![i], vec![i], None);
+        }
+
+        assert_eq!(shards.stores().len(), 4);
+        assert_eq!(shards.stats().unwrap().max_entries, Some(4));
+    }
+}
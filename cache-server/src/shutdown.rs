@@ -0,0 +1,29 @@
+use std::sync::atomic::{ AtomicBool, Ordering };
+#[cfg(unix)]
+use libc;
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Catches SIGINT and SIGTERM so that the server can finish up before it
+/// exits. Once installed, the process no longer exits on those signals by
+/// itself; something must poll `requested` and exit.
+#[cfg(unix)]
+pub fn install() {
+    unsafe {
+        libc::signal(libc::SIGINT, on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t);
+        libc::signal(libc::SIGTERM, on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t);
+    }
+}
+
+#[cfg(not(unix))]
+pub fn install() {}
+
+/// Whether a shutdown signal has arrived since `install`.
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
+
+#[cfg(unix)]
+extern "C" fn on_signal(_: libc::c_int) {
+    REQUESTED.store(true, Ordering::SeqCst);
+}
@@ -0,0 +1,251 @@
+use std::fs::{ self, File };
+use std::io::{ self, BufReader, BufWriter, Read, Write };
+use std::path::{ Path, PathBuf };
+use std::process;
+use std::sync::Arc;
+use std::thread;
+use std::time::{ Duration, Instant };
+use byteorder::{ BigEndian, ReadBytesExt, WriteBytesExt };
+use oplog::{ Fsync, OpLog };
+use shards::Shards;
+use shutdown;
+
+// A snapshot starts with these 8 bytes and a u32 format version. Each entry
+// follows as a record: a 1 byte tag of 1, then the key and the stored entry,
+// each as a u64 length and the bytes. The stored entry keeps the store's
+// header, so TTLs and versions survive a restart. A tag of 0 ends the file,
+// which tells a complete snapshot from a truncated one. Records appear shard
+// by shard, each shard's entries in the order its policy would evict them.
+const MAGIC: &'static [u8] = b"cLRUdsnp";
+const VERSION: u32 = 3;
+const ENTRY: u8 = 1;
+const END: u8 = 0;
+
+/// Writes every live entry to `path`, returning how many were written.
+///
+/// The snapshot is written to a temporary file beside `path` and renamed over
+/// it once complete, so a crash part way through leaves the previous
+/// snapshot in place. Shards are locked one at a time.
+pub fn save(shards: &Shards, path: &Path) -> io::Result<u64> {
+    let partial = path.with_extension("partial");
+    let mut out = BufWriter::new(File::create(&partial)?);
+    let written = write_to(shards, &mut out)?;
+
+    out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    fs::rename(&partial, path)?;
+    Ok(written)
+}
+
+/// Writes a snapshot of every live entry to `out`, returning how many were
+/// written.
+pub fn write_to<W: Write>(shards: &Shards, out: &mut W) -> io::Result<u64> {
+    out.write_all(MAGIC)?;
+    out.write_u32::<BigEndian>(VERSION)?;
+
+    let mut written = 0;
+    for shard in shards.stores() {
+        let entries = shard.lock().map_err(|_| poisoned())?.dump();
+        for (key, entry) in entries {
+            out.write_u8(ENTRY)?;
+            write_bytes(out, &key)?;
+            write_bytes(out, &entry)?;
+            written += 1;
+        }
+    }
+    out.write_u8(END)?;
+    Ok(written)
+}
+
+/// Stores every entry in the snapshot at `path` that has not expired since
+/// it was written, returning how many were stored.
+pub fn load(shards: &Shards, path: &Path) -> io::Result<u64> {
+    read_from(shards, &mut BufReader::new(File::open(path)?))
+}
+
+/// Stores every live entry of a snapshot read from `input`, as `load` does.
+pub fn read_from<R: Read>(shards: &Shards, input: &mut R) -> io::Result<u64> {
+    let mut magic = vec![0; MAGIC.len()];
+    input.read_exact(&mut magic)?;
+    if magic != MAGIC {
+        return Err(invalid("not a snapshot file".to_string()));
+    }
+    let version = input.read_u32::<BigEndian>()?;
+    if version != VERSION {
+        return Err(invalid(format!("unsupported snapshot version {}", version)));
+    }
+
+    let mut loaded = 0;
+    loop {
+        match input.read_u8()? {
+            ENTRY => {
+                let key = read_bytes(input)?;
+                let entry = read_bytes(input)?;
+                if shards.shard(&key).lock().map_err(|_| poisoned())?.load(key, entry) {
+                    loaded += 1;
+                }
+            },
+            END => return Ok(loaded),
+            tag => return Err(invalid(format!("unknown record tag {}", tag)))
+        }
+    }
+}
+
+/// Saves a snapshot to `path` every `interval`, if one is given, and once
+/// more when the server is asked to shut down, after which the process
+/// exits.
+///
+/// With an operation log, each snapshot compacts the log, as does the log
+/// outgrowing its compaction size, and the log is synced every second if its
+/// fsync policy asks for that.
+pub fn schedule(shards: Arc<Shards>, path: PathBuf, interval: Option<Duration>, log: Option<Arc<OpLog>>) {
+    thread::spawn(move || {
+        let mut last_saved = Instant::now();
+        let mut last_synced = Instant::now();
+        loop {
+            thread::sleep(Duration::from_millis(100));
+
+            if shutdown::requested() {
+                report(persist(&shards, &path, &log), &path);
+                process::exit(0);
+            }
+            let compact = log.as_ref().map_or(false, |log| log.wants_compaction());
+            if compact || interval.map_or(false, |interval| last_saved.elapsed() >= interval) {
+                report(persist(&shards, &path, &log), &path);
+                last_saved = Instant::now();
+            }
+            if let Some(ref log) = log {
+                if log.fsync() == Fsync::EverySecond && last_synced.elapsed() >= Duration::from_secs(1) {
+                    if let Err(e) = log.sync() {
+                        eprintln!("could not sync operation log: {}", e);
+                    }
+                    last_synced = Instant::now();
+                }
+            }
+        }
+    });
+}
+
+fn persist(shards: &Shards, path: &Path, log: &Option<Arc<OpLog>>) -> io::Result<u64> {
+    match *log {
+        Some(ref log) => log.compact(shards, path),
+        None => save(shards, path)
+    }
+}
+
+fn report(result: io::Result<u64>, path: &Path) {
+    match result {
+        Ok(count) => println!("saved {} entries to {}", count, path.display()),
+        Err(e) => eprintln!("could not save snapshot to {}: {}", path.display(), e)
+    }
+}
+
+pub fn write_bytes<W: Write>(out: &mut W, bytes: &[u8]) -> io::Result<()> {
+    out.write_u64::<BigEndian>(bytes.len() as u64)?;
+    out.write_all(bytes)
+}
+
+pub fn read_bytes<R: Read>(input: &mut R) -> io::Result<Vec<u8>> {
+    let length = input.read_u64::<BigEndian>()?;
+    let mut bytes = vec![];
+    input.take(length).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != length {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "snapshot is truncated"));
+    }
+    Ok(bytes)
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn poisoned() -> io::Error {
+    io::Error::new(io::ErrorKind::Other, "cache lock poisoned")
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs::{ self, File };
+    use std::io::{ ErrorKind, Write };
+    use std::path::PathBuf;
+    use std::process;
+    use std::time::Duration;
+    use shards::Shards;
+    use storage::{ Limits, Policy };
+    use store::Lookup;
+    use super::{ load, save };
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("clrud-{}-{}.snapshot", process::id(), name))
+    }
+
+    fn shards(capacity: u64) -> Shards {
+        Shards::new(1, Policy::Lru, Limits::new(Some(capacity), None))
+    }
+
+    fn put(shards: &Shards, key: &[u8], ttl: Option<Duration>) {
+        shards.shard(key).lock().unwrap().put(key.to_vec(), key.to_vec(), ttl);
+    }
+
+    fn get(shards: &Shards, key: &[u8]) -> Lookup {
+        shards.shard(key).lock().unwrap().get(&key.to_vec())
+    }
+
+    #[test]
+    fn test_round_trips_entries_in_lru_order() {
+        let path = temp_path("round-trip");
+        let original = shards(3);
+        put(&original, b"a", None);
+        put(&original, b"b", Some(Duration::from_secs(60)));
+        put(&original, b"c", None);
+        get(&original, b"a");
+        assert_eq!(save(&original, &path).unwrap(), 3);
+
+        let restored = shards(3);
+        assert_eq!(load(&restored, &path).unwrap(), 3);
+        put(&restored, b"d", None);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(get(&restored, b"b"), Lookup::Miss);
+        assert_eq!(get(&restored, b"a"), Lookup::Hit(b"a".to_vec()));
+        assert_eq!(get(&restored, b"c"), Lookup::Hit(b"c".to_vec()));
+    }
+
+    #[test]
+    fn test_restores_across_shard_counts() {
+        let path = temp_path("reshard");
+        let original = Shards::new(4, Policy::Lru, Limits::new(None, None));
+        for i in 0..50u8 {
+            put(&original, &[i], None);
+        }
+        save(&original, &path).unwrap();
+
+        let restored = Shards::new(3, Policy::Arc, Limits::new(None, None));
+        assert_eq!(load(&restored, &path).unwrap(), 50);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(get(&restored, &[42]), Lookup::Hit(vec![42]));
+    }
+
+    #[test]
+    fn test_rejects_other_versions() {
+        let path = temp_path("version");
+        File::create(&path).unwrap().write_all(b"cLRUdsnp\0\0\0\x09\0").unwrap();
+
+        let error = load(&shards(1), &path).unwrap_err();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_rejects_truncated_snapshot() {
+        let path = temp_path("truncated");
+        let original = shards(2);
+        put(&original, b"key", None);
+        save(&original, &path).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        fs::write(&path, &bytes[..bytes.len() - 1]).unwrap();
+
+        assert!(load(&shards(2), &path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,200 @@
+use std::cmp;
+use std::hash::Hash;
+use super::{ footprint, Entries, Limits, Queue, Storage };
+
+/// Adaptive replacement cache.
+///
+/// Entries seen once recently are kept in `recent` and entries seen at least
+/// twice in `frequent`, each in LRU order. The keys of entries evicted from
+/// either are remembered for a while as ghosts. A miss on a recent ghost
+/// means `recent` was too small, and one on a frequent ghost that `frequent`
+/// was, so each moves `target`, the number of entries `recent` is allowed to
+/// hold before it gives up entries ahead of `frequent`.
+///
+/// The original algorithm counts in entries. When the store is bounded only
+/// by bytes, the number of entries currently held stands in for its capacity.
+pub struct ArcCache<K> {
+    entries: Entries<K>,
+    recent: Queue<K>,
+    frequent: Queue<K>,
+    recent_ghosts: Queue<K>,
+    frequent_ghosts: Queue<K>,
+    target: u64,
+    limits: Limits
+}
+
+impl<K: Hash + Eq + Clone + AsRef<[u8]>> ArcCache<K> {
+    pub fn new(limits: Limits) -> Self {
+        ArcCache {
+            entries: Entries::new(),
+            recent: Queue::new(),
+            frequent: Queue::new(),
+            recent_ghosts: Queue::new(),
+            frequent_ghosts: Queue::new(),
+            target: 0,
+            limits: limits
+        }
+    }
+
+    fn capacity(&self) -> u64 {
+        self.limits.max_entries.unwrap_or(self.entries.len()).max(1)
+    }
+
+    fn promote(&mut self, key: &K) {
+        self.recent.remove(key);
+        self.frequent.push(key.clone());
+    }
+
+    /// Evicts entries until one more of `size` bytes fits.
+    fn make_room(&mut self, size: u64, frequent_ghost_hit: bool) {
+        while self.limits.exceeded(self.entries.len() + 1, self.entries.bytes() + size) {
+            if !self.replace(frequent_ghost_hit) {
+                return;
+            }
+        }
+    }
+
+    /// Evicts one entry, from `recent` if it has outgrown its target and from
+    /// `frequent` otherwise, and remembers its key as a ghost.
+    fn replace(&mut self, frequent_ghost_hit: bool) -> bool {
+        let recent = self.recent.len();
+        let from_recent = recent > 0 &&
+            (recent > self.target || (frequent_ghost_hit && recent == self.target) || self.frequent.len() == 0);
+
+        let evicted = if from_recent {
+            self.recent.pop_oldest().map(|key| {
+                self.recent_ghosts.push(key.clone());
+                key
+            })
+        } else {
+            self.frequent.pop_oldest().map(|key| {
+                self.frequent_ghosts.push(key.clone());
+                key
+            })
+        };
+        match evicted {
+            Some(key) => {
+                self.entries.evict(&key);
+                true
+            },
+            None => false
+        }
+    }
+
+    /// Forgets the oldest ghosts so that `recent` and its ghosts together
+    /// hold no more than the capacity, and everything no more than twice it.
+    fn trim_ghosts(&mut self) {
+        let capacity = self.capacity();
+        while self.recent.len() + self.recent_ghosts.len() > capacity && self.recent_ghosts.pop_oldest().is_some() {}
+
+        let total = |cache: &Self| {
+            cache.recent.len() + cache.frequent.len() + cache.recent_ghosts.len() + cache.frequent_ghosts.len()
+        };
+        while total(self) > 2 * capacity && self.frequent_ghosts.pop_oldest().is_some() {}
+    }
+}
+
+impl<K: Hash + Eq + Clone + AsRef<[u8]>> Storage<K> for ArcCache<K> {
+    fn put(&mut self, key: K, value: Vec<u8>) -> bool {
+        let size = footprint(&key, &value);
+        if !self.limits.admits(size) {
+            return false;
+        }
+
+        if self.entries.remove(&key).is_some() {
+            self.recent.remove(&key);
+            self.frequent.remove(&key);
+            self.make_room(size, false);
+            self.frequent.push(key.clone());
+        } else if self.recent_ghosts.remove(&key) {
+            let step = cmp::max(1, self.frequent_ghosts.len() / cmp::max(1, self.recent_ghosts.len()));
+            self.target = cmp::min(self.target + step, self.capacity());
+            self.make_room(size, false);
+            self.frequent.push(key.clone());
+        } else if self.frequent_ghosts.remove(&key) {
+            let step = cmp::max(1, self.recent_ghosts.len() / cmp::max(1, self.frequent_ghosts.len()));
+            self.target = self.target.saturating_sub(step);
+            self.make_room(size, true);
+            self.frequent.push(key.clone());
+        } else {
+            self.make_room(size, false);
+            self.recent.push(key.clone());
+        }
+
+        self.entries.insert(key, value);
+        self.trim_ghosts();
+        true
+    }
+
+    fn get(&mut self, key: &K) -> Option<&Vec<u8>> {
+        if self.entries.contains(key) {
+            self.promote(key);
+        }
+        self.entries.get(key)
+    }
+
+    fn remove(&mut self, key: &K) -> Option<Vec<u8>> {
+        self.recent.remove(key);
+        self.frequent.remove(key);
+        self.recent_ghosts.remove(key);
+        self.frequent_ghosts.remove(key);
+        self.entries.remove(key)
+    }
+
+    fn len(&self) -> u64 {
+        self.entries.len()
+    }
+
+    fn bytes(&self) -> u64 {
+        self.entries.bytes()
+    }
+
+    fn evictions(&self) -> u64 {
+        self.entries.evictions()
+    }
+
+    fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    fn eviction_order(&self) -> Vec<(&K, &Vec<u8>)> {
+        self.entries.in_order(self.recent.iter().chain(self.frequent.iter()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use storage::{ Limits, Storage };
+    use super::ArcCache;
+
+    #[test]
+    fn test_frequent_entries_survive_scan() {
+        let mut cache = ArcCache::new(Limits::new(Some(4), None));
+        cache.put(vec![0], vec![0]);
+        cache.put(vec![1], vec![1]);
+        cache.get(&vec![0]);
+        cache.get(&vec![1]);
+
+        for i in 10..50 {
+            cache.put(vec![i], vec![i]);
+        }
+
+        assert!(cache.get(&vec![0]).is_some());
+        assert!(cache.get(&vec![1]).is_some());
+    }
+
+    #[test]
+    fn test_recent_ghost_hit_grows_target() {
+        let mut cache = ArcCache::new(Limits::new(Some(2), None));
+        cache.put("a", vec![1]);
+        cache.get(&"a");
+        cache.put("b", vec![2]);
+        cache.put("c", vec![3]);
+        assert!(cache.get(&"b").is_none());
+        assert_eq!(cache.target, 0);
+
+        cache.put("b", vec![2]);
+        assert_eq!(cache.target, 1);
+        assert!(cache.frequent.contains(&"b"));
+    }
+}
@@ -0,0 +1,142 @@
+use std::collections::{ BTreeMap, HashMap };
+use std::hash::Hash;
+use super::{ footprint, Entries, Limits, Storage };
+
+/// Evicts the least frequently used entry first, breaking ties by evicting
+/// the less recently used.
+///
+/// Frequencies are counted from when an entry was stored, so an entry starts
+/// over at one each time it is evicted and stored again.
+pub struct LfuCache<K> {
+    entries: Entries<K>,
+    ranks: HashMap<K, (u64, u64)>,
+    order: BTreeMap<(u64, u64), K>,
+    clock: u64,
+    limits: Limits
+}
+
+impl<K: Hash + Eq + Clone + AsRef<[u8]>> LfuCache<K> {
+    pub fn new(limits: Limits) -> Self {
+        LfuCache {
+            entries: Entries::new(),
+            ranks: HashMap::new(),
+            order: BTreeMap::new(),
+            clock: 0,
+            limits: limits
+        }
+    }
+
+    /// Takes `key` out of the ranking, returning how often it had been used.
+    fn unrank(&mut self, key: &K) -> u64 {
+        match self.ranks.remove(key) {
+            Some(rank) => {
+                self.order.remove(&rank);
+                rank.0
+            },
+            None => 0
+        }
+    }
+
+    fn rank(&mut self, key: K, uses: u64) {
+        self.clock += 1;
+        let rank = (uses, self.clock);
+        self.order.insert(rank, key.clone());
+        self.ranks.insert(key, rank);
+    }
+
+    /// Evicts entries until one more of `size` bytes fits. The new entry is
+    /// left out of the running, since it would always have the lowest count.
+    fn make_room(&mut self, size: u64) {
+        while self.limits.exceeded(self.entries.len() + 1, self.entries.bytes() + size) {
+            let lowest = match self.order.keys().next() {
+                Some(rank) => *rank,
+                None => return
+            };
+            if let Some(key) = self.order.remove(&lowest) {
+                self.ranks.remove(&key);
+                self.entries.evict(&key);
+            }
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone + AsRef<[u8]>> Storage<K> for LfuCache<K> {
+    fn put(&mut self, key: K, value: Vec<u8>) -> bool {
+        let size = footprint(&key, &value);
+        if !self.limits.admits(size) {
+            return false;
+        }
+
+        let uses = self.unrank(&key);
+        self.entries.remove(&key);
+        self.make_room(size);
+        self.rank(key.clone(), uses + 1);
+        self.entries.insert(key, value);
+        true
+    }
+
+    fn get(&mut self, key: &K) -> Option<&Vec<u8>> {
+        if self.entries.contains(key) {
+            let uses = self.unrank(key);
+            self.rank(key.clone(), uses + 1);
+        }
+        self.entries.get(key)
+    }
+
+    fn remove(&mut self, key: &K) -> Option<Vec<u8>> {
+        self.unrank(key);
+        self.entries.remove(key)
+    }
+
+    fn len(&self) -> u64 {
+        self.entries.len()
+    }
+
+    fn bytes(&self) -> u64 {
+        self.entries.bytes()
+    }
+
+    fn evictions(&self) -> u64 {
+        self.entries.evictions()
+    }
+
+    fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    fn eviction_order(&self) -> Vec<(&K, &Vec<u8>)> {
+        self.entries.in_order(self.order.values())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use storage::{ Limits, Storage };
+    use super::LfuCache;
+
+    #[test]
+    fn test_evicts_least_frequently_used_entry() {
+        let mut cache = LfuCache::new(Limits::new(Some(2), None));
+        cache.put("a", vec![1]);
+        cache.put("b", vec![2]);
+        cache.get(&"a");
+        cache.get(&"b");
+        cache.get(&"a");
+        cache.put("c", vec![3]);
+
+        assert!(cache.get(&"a").is_some());
+        assert!(cache.get(&"b").is_none());
+        assert!(cache.get(&"c").is_some());
+    }
+
+    #[test]
+    fn test_breaks_ties_by_recency() {
+        let mut cache = LfuCache::new(Limits::new(Some(2), None));
+        cache.put("a", vec![1]);
+        cache.put("b", vec![2]);
+        cache.put("c", vec![3]);
+
+        assert!(cache.get(&"a").is_none());
+        assert!(cache.get(&"b").is_some());
+    }
+}
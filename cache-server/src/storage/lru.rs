@@ -0,0 +1,125 @@
+use std::hash::Hash;
+use super::{ footprint, Entries, Limits, Queue, Storage };
+
+/// Evicts the least recently used entry first.
+pub struct LruCache<K> {
+    entries: Entries<K>,
+    recency: Queue<K>,
+    limits: Limits
+}
+
+impl<K: Hash + Eq + Clone + AsRef<[u8]>> LruCache<K> {
+    pub fn new(limits: Limits) -> Self {
+        LruCache {
+            entries: Entries::new(),
+            recency: Queue::new(),
+            limits: limits
+        }
+    }
+
+    fn evict(&mut self) {
+        while self.limits.exceeded(self.entries.len(), self.entries.bytes()) {
+            match self.recency.pop_oldest() {
+                Some(key) => self.entries.evict(&key),
+                None => return
+            }
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone + AsRef<[u8]>> Storage<K> for LruCache<K> {
+    fn put(&mut self, key: K, value: Vec<u8>) -> bool {
+        if !self.limits.admits(footprint(&key, &value)) {
+            return false;
+        }
+
+        self.recency.push(key.clone());
+        self.entries.insert(key, value);
+        self.evict();
+        true
+    }
+
+    fn get(&mut self, key: &K) -> Option<&Vec<u8>> {
+        if self.entries.contains(key) {
+            self.recency.push(key.clone());
+        }
+        self.entries.get(key)
+    }
+
+    fn remove(&mut self, key: &K) -> Option<Vec<u8>> {
+        self.recency.remove(key);
+        self.entries.remove(key)
+    }
+
+    fn len(&self) -> u64 {
+        self.entries.len()
+    }
+
+    fn bytes(&self) -> u64 {
+        self.entries.bytes()
+    }
+
+    fn evictions(&self) -> u64 {
+        self.entries.evictions()
+    }
+
+    fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    fn eviction_order(&self) -> Vec<(&K, &Vec<u8>)> {
+        self.entries.in_order(self.recency.iter())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use storage::{ footprint, Limits, Storage };
+    use super::LruCache;
+
+    #[test]
+    fn test_evicts_least_recently_used_entry() {
+        let mut cache = LruCache::new(Limits::new(Some(2), None));
+        cache.put("a", vec![1]);
+        cache.put("b", vec![2]);
+        cache.get(&"a");
+        cache.put("c", vec![3]);
+
+        assert_eq!(cache.get(&"a"), Some(&vec![1]));
+        assert_eq!(cache.get(&"b"), None);
+        assert_eq!(cache.get(&"c"), Some(&vec![3]));
+    }
+
+    #[test]
+    fn test_evicts_until_under_byte_limit() {
+        let small = footprint(&"a", &vec![0; 4]);
+        let large = footprint(&"c", &vec![0; 8]);
+        let mut cache = LruCache::new(Limits::new(None, Some(small + large)));
+        cache.put("a", vec![0; 4]);
+        cache.put("b", vec![0; 4]);
+        cache.put("c", vec![0; 8]);
+
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.bytes(), small + large);
+        assert_eq!(cache.evictions(), 1);
+        assert!(cache.get(&"a").is_none());
+    }
+
+    #[test]
+    fn test_counts_key_value_and_overhead() {
+        let mut cache = LruCache::new(Limits::new(None, None));
+        cache.put("key", vec![0; 10]);
+
+        assert!(cache.bytes() > 3 * 2 + 10);
+        assert_eq!(cache.bytes(), footprint(&"key", &vec![0; 10]));
+    }
+
+    #[test]
+    fn test_rejects_entry_over_byte_limit() {
+        let mut cache = LruCache::new(Limits::new(None, Some(footprint(&"a", &vec![1]))));
+        cache.put("a", vec![1]);
+
+        assert!(!cache.put("b", vec![0; 5]));
+        assert_eq!(cache.get(&"a"), Some(&vec![1]));
+    }
+}
@@ -0,0 +1,303 @@
+use std::collections::{ btree_map, BTreeMap, HashMap };
+use std::hash::Hash;
+use std::mem;
+
+mod arc;
+mod lfu;
+mod lru;
+mod tiny_lfu;
+mod two_queue;
+
+pub use self::arc::ArcCache;
+pub use self::lfu::LfuCache;
+pub use self::lru::LruCache;
+pub use self::tiny_lfu::TinyLfuCache;
+pub use self::two_queue::TwoQueueCache;
+
+/// A bounded key-value store that decides for itself which entries to evict
+/// once it is full.
+pub trait Storage<K> {
+    /// Stores `value` under `key`, evicting other entries to make room.
+    /// Returns false, leaving the store unchanged, if the entry alone would
+    /// exceed the byte limit.
+    fn put(&mut self, key: K, value: Vec<u8>) -> bool;
+    fn get(&mut self, key: &K) -> Option<&Vec<u8>>;
+    fn remove(&mut self, key: &K) -> Option<Vec<u8>>;
+    fn len(&self) -> u64;
+    /// Memory currently taken up by entries, as counted by `footprint`.
+    fn bytes(&self) -> u64;
+    /// Number of entries evicted to stay within the limits so far.
+    fn evictions(&self) -> u64;
+    fn limits(&self) -> Limits;
+    /// Every entry, roughly in the order the policy would evict them. Storing
+    /// them again in this order into an empty store of the same policy
+    /// rebuilds much the same state.
+    fn eviction_order(&self) -> Vec<(&K, &Vec<u8>)>;
+}
+
+/// The eviction policies a store can be built with.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Policy {
+    Lru,
+    Lfu,
+    Arc,
+    TwoQueue,
+    TinyLfu
+}
+
+impl Policy {
+    pub fn names() -> &'static [&'static str] {
+        &["lru", "lfu", "arc", "2q", "w-tinylfu"]
+    }
+
+    pub fn from_name(name: &str) -> Option<Policy> {
+        match name {
+            "lru" => Some(Policy::Lru),
+            "lfu" => Some(Policy::Lfu),
+            "arc" => Some(Policy::Arc),
+            "2q" => Some(Policy::TwoQueue),
+            "w-tinylfu" => Some(Policy::TinyLfu),
+            _ => None
+        }
+    }
+
+    pub fn build<K>(&self, limits: Limits) -> Box<dyn Storage<K> + Send>
+        where K: Hash + Eq + Clone + AsRef<[u8]> + Send + 'static {
+        match *self {
+            Policy::Lru => Box::new(LruCache::new(limits)),
+            Policy::Lfu => Box::new(LfuCache::new(limits)),
+            Policy::Arc => Box::new(ArcCache::new(limits)),
+            Policy::TwoQueue => Box::new(TwoQueueCache::new(limits)),
+            Policy::TinyLfu => Box::new(TinyLfuCache::new(limits))
+        }
+    }
+}
+
+/// How large a store may grow. A limit of `None` leaves that dimension
+/// unbounded.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Limits {
+    pub max_entries: Option<u64>,
+    pub max_bytes: Option<u64>
+}
+
+impl Limits {
+    pub fn new(max_entries: Option<u64>, max_bytes: Option<u64>) -> Self {
+        Limits { max_entries: max_entries, max_bytes: max_bytes }
+    }
+
+    /// Whether an entry of `size` bytes could be stored at all.
+    pub fn admits(&self, size: u64) -> bool {
+        self.max_bytes.map_or(true, |max| size <= max)
+    }
+
+    pub fn exceeded(&self, entries: u64, bytes: u64) -> bool {
+        self.max_entries.map_or(false, |max| entries > max) ||
+            self.max_bytes.map_or(false, |max| bytes > max)
+    }
+}
+
+/// The memory counted against the byte limit for one entry: its key, which
+/// the policies hold twice, its value, and a fixed allowance for the slots it
+/// takes in their maps and queues. Allocator and hash table slack, and the
+/// keys some policies remember after eviction, are left out, so actual usage
+/// runs somewhat higher.
+pub fn footprint<K: AsRef<[u8]>>(key: &K, value: &Vec<u8>) -> u64 {
+    let slots = mem::size_of::<(K, Vec<u8>)>() + mem::size_of::<(K, u64)>() + mem::size_of::<(u64, K)>();
+    (slots + 2 * key.as_ref().len() + value.len()) as u64
+}
+
+/// The resident entries of a store and the running totals reported in stats.
+/// Policies keep their ordering separately and use this for the values.
+pub struct Entries<K> {
+    values: HashMap<K, Vec<u8>>,
+    bytes: u64,
+    evictions: u64
+}
+
+impl<K: Hash + Eq + AsRef<[u8]>> Entries<K> {
+    pub fn new() -> Self {
+        Entries { values: HashMap::new(), bytes: 0, evictions: 0 }
+    }
+
+    /// Stores `value`, returning the value it replaced.
+    pub fn insert(&mut self, key: K, value: Vec<u8>) -> Option<Vec<u8>> {
+        self.bytes += footprint(&key, &value);
+        let replaced = self.values.remove(&key);
+        if let Some(ref old) = replaced {
+            self.bytes -= footprint(&key, old);
+        }
+        self.values.insert(key, value);
+        replaced
+    }
+
+    pub fn get(&self, key: &K) -> Option<&Vec<u8>> {
+        self.values.get(key)
+    }
+
+    pub fn contains(&self, key: &K) -> bool {
+        self.values.contains_key(key)
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<Vec<u8>> {
+        self.values.remove(key).map(|value| {
+            self.bytes -= footprint(key, &value);
+            value
+        })
+    }
+
+    /// Removes `key` to make room, counting it as an eviction.
+    pub fn evict(&mut self, key: &K) {
+        if self.remove(key).is_some() {
+            self.evictions += 1;
+        }
+    }
+
+    pub fn len(&self) -> u64 {
+        self.values.len() as u64
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    pub fn evictions(&self) -> u64 {
+        self.evictions
+    }
+
+    /// Pairs each of `keys` that is resident with its value.
+    pub fn in_order<'a, I: Iterator<Item=&'a K>>(&'a self, keys: I) -> Vec<(&'a K, &'a Vec<u8>)> {
+        keys.filter_map(|key| self.values.get(key).map(|value| (key, value))).collect()
+    }
+}
+
+/// A set of keys kept in the order they were last pushed.
+///
+/// Every push stamps the key with the next tick of a logical clock, so the
+/// key with the lowest stamp is the oldest. Pushing a key that is already
+/// queued moves it to the back. Pushing only on insert gives a FIFO queue;
+/// pushing on every access as well gives an LRU queue.
+pub struct Queue<K> {
+    order: BTreeMap<u64, K>,
+    ticks: HashMap<K, u64>,
+    clock: u64
+}
+
+impl<K: Hash + Eq + Clone> Queue<K> {
+    pub fn new() -> Self {
+        Queue { order: BTreeMap::new(), ticks: HashMap::new(), clock: 0 }
+    }
+
+    pub fn push(&mut self, key: K) {
+        self.remove(&key);
+        self.clock += 1;
+        self.order.insert(self.clock, key.clone());
+        self.ticks.insert(key, self.clock);
+    }
+
+    pub fn remove(&mut self, key: &K) -> bool {
+        match self.ticks.remove(key) {
+            Some(tick) => {
+                self.order.remove(&tick);
+                true
+            },
+            None => false
+        }
+    }
+
+    pub fn contains(&self, key: &K) -> bool {
+        self.ticks.contains_key(key)
+    }
+
+    pub fn oldest(&self) -> Option<&K> {
+        self.order.values().next()
+    }
+
+    pub fn pop_oldest(&mut self) -> Option<K> {
+        let oldest = match self.order.keys().next() {
+            Some(tick) => *tick,
+            None => return None
+        };
+        self.order.remove(&oldest).map(|key| {
+            self.ticks.remove(&key);
+            key
+        })
+    }
+
+    pub fn len(&self) -> u64 {
+        self.ticks.len() as u64
+    }
+
+    /// The keys from oldest to newest.
+    pub fn iter(&self) -> btree_map::Values<'_, u64, K> {
+        self.order.values()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ footprint, Limits, Policy, Queue };
+
+    fn each_policy<F: Fn(Policy)>(check: F) {
+        for name in Policy::names() {
+            check(Policy::from_name(name).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_queue_orders_by_last_push() {
+        let mut queue = Queue::new();
+        queue.push("a");
+        queue.push("b");
+        queue.push("a");
+
+        assert_eq!(queue.pop_oldest(), Some("b"));
+        assert_eq!(queue.pop_oldest(), Some("a"));
+        assert_eq!(queue.pop_oldest(), None);
+    }
+
+    #[test]
+    fn test_policies_store_and_remove() {
+        each_policy(|policy| {
+            let mut cache = policy.build(Limits::new(Some(8), None));
+            assert!(cache.put("a", vec![1]));
+            cache.put("a", vec![2]);
+
+            assert_eq!(cache.get(&"a"), Some(&vec![2]), "{:?}", policy);
+            assert_eq!(cache.len(), 1);
+            assert_eq!(cache.bytes(), footprint(&"a", &vec![2]));
+            assert_eq!(cache.remove(&"a"), Some(vec![2]));
+            assert_eq!(cache.get(&"a"), None);
+            assert_eq!(cache.bytes(), 0);
+        });
+    }
+
+    #[test]
+    fn test_policies_stay_within_entry_limit() {
+        each_policy(|policy| {
+            let mut cache = policy.build(Limits::new(Some(10), None));
+            for i in 0..100u8 {
+                cache.put(vec![i], vec![i]);
+                cache.get(&vec![i / 2]);
+            }
+
+            assert_eq!(cache.len(), 10, "{:?}", policy);
+            assert_eq!(cache.evictions(), 90, "{:?}", policy);
+        });
+    }
+
+    #[test]
+    fn test_policies_stay_within_byte_limit() {
+        each_policy(|policy| {
+            let max = 10 * footprint(&vec![0u8], &vec![0; 16]);
+            let mut cache = policy.build(Limits::new(None, Some(max)));
+            for i in 0..100u8 {
+                cache.put(vec![i], vec![0; 16]);
+            }
+
+            assert!(cache.bytes() <= max, "{:?}", policy);
+            assert_eq!(cache.len(), 10, "{:?}", policy);
+            assert!(!cache.put(vec![0], vec![0; max as usize]), "{:?}", policy);
+        });
+    }
+}
@@ -0,0 +1,257 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{ Hash, Hasher };
+use super::{ footprint, Entries, Limits, Queue, Storage };
+
+const SKETCH_DEPTH: usize = 4;
+const MAX_COUNT: u8 = 15;
+
+/// W-TinyLFU.
+///
+/// New entries land in a small LRU `window`, about one percent of the store.
+/// Entries pushed out of the window join the main space, a segmented LRU
+/// whose `probation` segment holds entries seen there once and whose
+/// `protected` segment, about eighty percent of the main space, holds those
+/// hit again since. When the store is full, an entry leaving the window is
+/// only admitted if a frequency sketch of recent traffic rates it above the
+/// entry probation would evict in its place, so one-off keys cannot flush
+/// out popular ones.
+///
+/// Shares are counted in entries. When the store is bounded only by bytes,
+/// the number of entries currently held stands in for its capacity.
+pub struct TinyLfuCache<K> {
+    entries: Entries<K>,
+    window: Queue<K>,
+    probation: Queue<K>,
+    protected: Queue<K>,
+    sketch: FrequencySketch,
+    limits: Limits
+}
+
+impl<K: Hash + Eq + Clone + AsRef<[u8]>> TinyLfuCache<K> {
+    pub fn new(limits: Limits) -> Self {
+        TinyLfuCache {
+            entries: Entries::new(),
+            window: Queue::new(),
+            probation: Queue::new(),
+            protected: Queue::new(),
+            sketch: FrequencySketch::new(limits.max_entries.unwrap_or(4096)),
+            limits: limits
+        }
+    }
+
+    fn capacity(&self) -> u64 {
+        self.limits.max_entries.unwrap_or(self.entries.len()).max(1)
+    }
+
+    fn window_share(&self) -> u64 {
+        (self.capacity() / 100).max(1)
+    }
+
+    fn protected_share(&self) -> u64 {
+        (self.capacity().saturating_sub(self.window_share()) * 8 / 10).max(1)
+    }
+
+    fn touch(&mut self, key: &K) {
+        if self.window.contains(key) {
+            self.window.push(key.clone());
+        } else if self.protected.contains(key) {
+            self.protected.push(key.clone());
+        } else if self.probation.remove(key) {
+            self.protected.push(key.clone());
+            while self.protected.len() > self.protected_share() {
+                match self.protected.pop_oldest() {
+                    Some(demoted) => self.probation.push(demoted),
+                    None => break
+                }
+            }
+        }
+    }
+
+    /// The entry the main space would give up first.
+    fn victim(&self) -> Option<K> {
+        self.probation.oldest().or_else(|| self.protected.oldest()).cloned()
+    }
+
+    fn evict(&mut self, key: &K) {
+        self.probation.remove(key);
+        self.protected.remove(key);
+        self.window.remove(key);
+        self.entries.evict(key);
+    }
+
+    /// Moves entries that have outgrown the window into the main space, then
+    /// evicts until the store is back within its limits.
+    fn rebalance(&mut self) {
+        while self.window.len() > self.window_share() {
+            let candidate = match self.window.pop_oldest() {
+                Some(candidate) => candidate,
+                None => break
+            };
+            if !self.limits.exceeded(self.entries.len(), self.entries.bytes()) {
+                self.probation.push(candidate);
+                continue;
+            }
+
+            match self.victim() {
+                Some(ref victim) if self.sketch.frequency(&candidate) > self.sketch.frequency(victim) => {
+                    self.evict(victim);
+                    self.probation.push(candidate);
+                },
+                Some(_) => self.entries.evict(&candidate),
+                None => self.probation.push(candidate)
+            }
+        }
+
+        while self.limits.exceeded(self.entries.len(), self.entries.bytes()) {
+            match self.victim().or_else(|| self.window.oldest().cloned()) {
+                Some(victim) => self.evict(&victim),
+                None => return
+            }
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone + AsRef<[u8]>> Storage<K> for TinyLfuCache<K> {
+    fn put(&mut self, key: K, value: Vec<u8>) -> bool {
+        if !self.limits.admits(footprint(&key, &value)) {
+            return false;
+        }
+
+        self.sketch.increment(&key);
+        if self.entries.contains(&key) {
+            self.touch(&key);
+        } else {
+            self.window.push(key.clone());
+        }
+        self.entries.insert(key, value);
+        self.rebalance();
+        true
+    }
+
+    fn get(&mut self, key: &K) -> Option<&Vec<u8>> {
+        self.sketch.increment(key);
+        if self.entries.contains(key) {
+            self.touch(key);
+        }
+        self.entries.get(key)
+    }
+
+    fn remove(&mut self, key: &K) -> Option<Vec<u8>> {
+        self.window.remove(key);
+        self.probation.remove(key);
+        self.protected.remove(key);
+        self.entries.remove(key)
+    }
+
+    fn len(&self) -> u64 {
+        self.entries.len()
+    }
+
+    fn bytes(&self) -> u64 {
+        self.entries.bytes()
+    }
+
+    fn evictions(&self) -> u64 {
+        self.entries.evictions()
+    }
+
+    fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    fn eviction_order(&self) -> Vec<(&K, &Vec<u8>)> {
+        let main = self.probation.iter().chain(self.protected.iter());
+        self.entries.in_order(main.chain(self.window.iter()))
+    }
+}
+
+/// A count-min sketch of how often keys have been seen lately.
+///
+/// Each key maps to one small counter in each of several rows, and its
+/// estimate is the lowest of them. Once the number of increments reaches ten
+/// times the width, every counter is halved so that old popularity fades.
+struct FrequencySketch {
+    counters: Vec<u8>,
+    width: usize,
+    additions: u64
+}
+
+impl FrequencySketch {
+    fn new(expected_entries: u64) -> Self {
+        let width = (expected_entries.max(16).min(1 << 24) as usize).next_power_of_two();
+        FrequencySketch {
+            counters: vec![0; width * SKETCH_DEPTH],
+            width: width,
+            additions: 0
+        }
+    }
+
+    fn increment<K: Hash>(&mut self, key: &K) {
+        for row in 0..SKETCH_DEPTH {
+            let index = self.index(key, row);
+            if self.counters[index] < MAX_COUNT {
+                self.counters[index] += 1;
+            }
+        }
+
+        self.additions += 1;
+        if self.additions >= 10 * self.width as u64 {
+            for counter in self.counters.iter_mut() {
+                *counter /= 2;
+            }
+            self.additions /= 2;
+        }
+    }
+
+    fn frequency<K: Hash>(&self, key: &K) -> u8 {
+        (0..SKETCH_DEPTH).map(|row| self.counters[self.index(key, row)]).min().unwrap_or(0)
+    }
+
+    fn index<K: Hash>(&self, key: &K, row: usize) -> usize {
+        let mut hasher = DefaultHasher::new();
+        row.hash(&mut hasher);
+        key.hash(&mut hasher);
+        row * self.width + (hasher.finish() as usize & (self.width - 1))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use storage::{ Limits, Storage };
+    use super::{ FrequencySketch, TinyLfuCache };
+
+    #[test]
+    fn test_popular_entries_survive_scan() {
+        let mut cache = TinyLfuCache::new(Limits::new(Some(10), None));
+        for i in 0..5 {
+            cache.put(vec![i], vec![i]);
+        }
+        for _ in 0..3 {
+            for i in 0..5 {
+                cache.get(&vec![i]);
+            }
+        }
+
+        for i in 10..100 {
+            cache.put(vec![i], vec![i]);
+        }
+
+        for i in 0..5 {
+            assert!(cache.get(&vec![i]).is_some(), "lost {}", i);
+        }
+    }
+
+    #[test]
+    fn test_sketch_counts_saturate_and_age() {
+        let mut sketch = FrequencySketch::new(16);
+        for _ in 0..20 {
+            sketch.increment(&"hot");
+        }
+        assert_eq!(sketch.frequency(&"hot"), 15);
+
+        for i in 0..140 {
+            sketch.increment(&i);
+        }
+        assert!(sketch.frequency(&"hot") <= 8);
+    }
+}
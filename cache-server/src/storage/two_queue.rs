@@ -0,0 +1,160 @@
+use std::hash::Hash;
+use super::{ footprint, Entries, Limits, Queue, Storage };
+
+/// The full version of the 2Q algorithm.
+///
+/// New entries go into `incoming`, a FIFO queue that is not reordered by
+/// hits, so a burst of accesses to an entry counts as one. Entries pushed out
+/// of `incoming` leave their key in `ghosts`. A key that is stored again while
+/// it is still a ghost has been wanted twice over a longer span, and goes
+/// into `frequent`, which is kept in LRU order. Entries are evicted from
+/// `incoming` while it holds more than a quarter of the store, and from
+/// `frequent` otherwise. One-off scans therefore only ever displace each
+/// other.
+///
+/// Shares are counted in entries. When the store is bounded only by bytes,
+/// the number of entries currently held stands in for its capacity.
+pub struct TwoQueueCache<K> {
+    entries: Entries<K>,
+    incoming: Queue<K>,
+    ghosts: Queue<K>,
+    frequent: Queue<K>,
+    limits: Limits
+}
+
+impl<K: Hash + Eq + Clone + AsRef<[u8]>> TwoQueueCache<K> {
+    pub fn new(limits: Limits) -> Self {
+        TwoQueueCache {
+            entries: Entries::new(),
+            incoming: Queue::new(),
+            ghosts: Queue::new(),
+            frequent: Queue::new(),
+            limits: limits
+        }
+    }
+
+    fn capacity(&self) -> u64 {
+        self.limits.max_entries.unwrap_or(self.entries.len()).max(1)
+    }
+
+    /// Evicts entries until one more of `size` bytes fits.
+    fn make_room(&mut self, size: u64) {
+        while self.limits.exceeded(self.entries.len() + 1, self.entries.bytes() + size) {
+            let incoming_share = (self.capacity() / 4).max(1);
+            let from_incoming = self.incoming.len() > incoming_share || self.frequent.len() == 0;
+
+            let evicted = if from_incoming {
+                self.incoming.pop_oldest().map(|key| {
+                    self.ghosts.push(key.clone());
+                    key
+                })
+            } else {
+                self.frequent.pop_oldest()
+            };
+            match evicted {
+                Some(key) => self.entries.evict(&key),
+                None => return
+            }
+        }
+
+        let ghost_share = (self.capacity() / 2).max(1);
+        while self.ghosts.len() > ghost_share && self.ghosts.pop_oldest().is_some() {}
+    }
+}
+
+impl<K: Hash + Eq + Clone + AsRef<[u8]>> Storage<K> for TwoQueueCache<K> {
+    fn put(&mut self, key: K, value: Vec<u8>) -> bool {
+        let size = footprint(&key, &value);
+        if !self.limits.admits(size) {
+            return false;
+        }
+
+        if self.entries.remove(&key).is_some() {
+            let frequent = self.frequent.remove(&key);
+            self.incoming.remove(&key);
+            self.make_room(size);
+            if frequent {
+                self.frequent.push(key.clone());
+            } else {
+                self.incoming.push(key.clone());
+            }
+        } else if self.ghosts.remove(&key) {
+            self.make_room(size);
+            self.frequent.push(key.clone());
+        } else {
+            self.make_room(size);
+            self.incoming.push(key.clone());
+        }
+
+        self.entries.insert(key, value);
+        true
+    }
+
+    fn get(&mut self, key: &K) -> Option<&Vec<u8>> {
+        if self.frequent.contains(key) {
+            self.frequent.push(key.clone());
+        }
+        self.entries.get(key)
+    }
+
+    fn remove(&mut self, key: &K) -> Option<Vec<u8>> {
+        self.incoming.remove(key);
+        self.ghosts.remove(key);
+        self.frequent.remove(key);
+        self.entries.remove(key)
+    }
+
+    fn len(&self) -> u64 {
+        self.entries.len()
+    }
+
+    fn bytes(&self) -> u64 {
+        self.entries.bytes()
+    }
+
+    fn evictions(&self) -> u64 {
+        self.entries.evictions()
+    }
+
+    fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    fn eviction_order(&self) -> Vec<(&K, &Vec<u8>)> {
+        self.entries.in_order(self.incoming.iter().chain(self.frequent.iter()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use storage::{ Limits, Storage };
+    use super::TwoQueueCache;
+
+    #[test]
+    fn test_ghost_hit_promotes_to_frequent() {
+        let mut cache = TwoQueueCache::new(Limits::new(Some(4), None));
+        for i in 0..5 {
+            cache.put(vec![i], vec![i]);
+        }
+        assert!(cache.get(&vec![0]).is_none());
+        assert!(cache.ghosts.contains(&vec![0]));
+
+        cache.put(vec![0], vec![0]);
+        assert!(cache.frequent.contains(&vec![0]));
+    }
+
+    #[test]
+    fn test_frequent_entries_survive_scan() {
+        let mut cache = TwoQueueCache::new(Limits::new(Some(4), None));
+        for i in 0..5 {
+            cache.put(vec![i], vec![i]);
+        }
+        cache.put(vec![0], vec![0]);
+
+        for i in 10..50 {
+            cache.put(vec![i], vec![i]);
+        }
+
+        assert!(cache.get(&vec![0]).is_some());
+    }
+}
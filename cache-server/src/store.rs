@@ -0,0 +1,453 @@
+use std::time::{ Duration, SystemTime, UNIX_EPOCH };
+use byteorder::{ ByteOrder, BigEndian };
+use cache_codec::counter;
+use storage::{ footprint, Limits, Policy, Storage };
+
+// Each value is stored behind a 17 byte header holding its expiry as
+// milliseconds since the epoch, where 0 means the entry never expires, then
+// its version, then a byte of flags saying what the value holds.
+const HEADER_LENGTH: usize = 17;
+const FLAGS: usize = 16;
+// Set in an entry's flags when its value is a counter made by INCR or DECR.
+const COUNTER: u8 = 1;
+
+#[derive(PartialEq, Eq, Debug)]
+pub enum Lookup<T = Vec<u8>> {
+    Hit(T),
+    Expired,
+    Miss
+}
+
+/// A snapshot of how full the store is.
+#[derive(PartialEq, Eq, Debug)]
+pub struct Stats {
+    pub entries: u64,
+    pub bytes: u64,
+    pub evictions: u64,
+    pub max_entries: Option<u64>,
+    pub max_bytes: Option<u64>
+}
+
+impl Stats {
+    /// Renders the stats as `name value` lines, leaving out unset limits.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut lines = format!("entries {}\nbytes {}\nevictions {}\n",
+                                self.entries, self.bytes, self.evictions);
+        if let Some(max) = self.max_entries {
+            lines.push_str(&format!("max_entries {}\n", max));
+        }
+        if let Some(max) = self.max_bytes {
+            lines.push_str(&format!("max_bytes {}\n", max));
+        }
+        lines.into_bytes()
+    }
+}
+
+pub struct Store {
+    cache: Box<dyn Storage<Vec<u8>> + Send>,
+    version: u64
+}
+
+impl Store {
+    /// An LRU store holding at most `capacity` entries.
+    #[cfg(test)]
+    pub fn new(capacity: u64) -> Self {
+        Store::with_policy(Policy::Lru, Limits::new(Some(capacity), None))
+    }
+
+    /// A store bounded by entry count, memory used in bytes, or both, that
+    /// evicts according to `policy`. Memory covers keys, values, and a fixed
+    /// overhead per entry.
+    pub fn with_policy(policy: Policy, limits: Limits) -> Self {
+        // Versions count up from the time the store was made, so a version
+        // handed out before a restart is not handed out again after it.
+        Store { cache: policy.build(limits), version: now() * 1000 }
+    }
+
+    /// Returns false if the value is too large to be stored at all.
+    #[cfg(test)]
+    pub fn put(&mut self, key: Vec<u8>, value: Vec<u8>, ttl: Option<Duration>) -> bool {
+        let entry = self.entry(value, ttl);
+        self.put_entry(key, entry)
+    }
+
+    /// A value behind the header recording when it expires, `ttl` from now,
+    /// and a version no earlier entry in this store has had.
+    pub fn entry(&mut self, value: Vec<u8>, ttl: Option<Duration>) -> Vec<u8> {
+        entry(value, ttl, self.next_version())
+    }
+
+    /// An entry as `entry` makes, holding `count` and marked as a counter.
+    pub fn counter(&mut self, count: u64, ttl: Option<Duration>) -> Vec<u8> {
+        let mut entry = self.entry(counter::encode_value(count), ttl);
+        entry[FLAGS] = COUNTER;
+        entry
+    }
+
+    /// Whether `put_entry` would store `entry` under `key`, rather than find
+    /// it too large to be stored at all.
+    pub fn admits(&self, key: &Vec<u8>, entry: &Vec<u8>) -> bool {
+        self.cache.limits().admits(footprint(key, entry))
+    }
+
+    /// Stores an entry built by `entry`. Returns false if it is too large to
+    /// be stored at all.
+    pub fn put_entry(&mut self, key: Vec<u8>, entry: Vec<u8>) -> bool {
+        self.version = u64::max(self.version, version(&entry));
+        self.cache.put(key, entry)
+    }
+
+    #[cfg(test)]
+    pub fn get(&mut self, key: &Vec<u8>) -> Lookup {
+        match self.get_versioned(key) {
+            Lookup::Hit((value, _)) => Lookup::Hit(value),
+            Lookup::Expired => Lookup::Expired,
+            Lookup::Miss => Lookup::Miss
+        }
+    }
+
+    /// Looks up `key` as `get` does, giving a hit's version with its value.
+    pub fn get_versioned(&mut self, key: &Vec<u8>) -> Lookup<(Vec<u8>, u64)> {
+        match self.get_entry(key) {
+            Lookup::Hit(mut entry) => {
+                let value = entry.split_off(HEADER_LENGTH);
+                Lookup::Hit((value, version(&entry)))
+            },
+            Lookup::Expired => Lookup::Expired,
+            Lookup::Miss => Lookup::Miss
+        }
+    }
+
+    /// Looks up `key` as `get` does, giving a hit's whole entry, header
+    /// included, to read with `value` and change with `revise`.
+    pub fn get_entry(&mut self, key: &Vec<u8>) -> Lookup {
+        let found = self.cache.get(key).map(|entry| (is_live(entry), entry.clone()));
+
+        match found {
+            Some((true, entry)) => Lookup::Hit(entry),
+            Some((false, _)) => {
+                self.cache.remove(key);
+                Lookup::Expired
+            },
+            None => Lookup::Miss
+        }
+    }
+
+    /// A new version of `entry` holding `value` instead, that expires when
+    /// `entry` would have. The value is no longer a counter.
+    pub fn revise(&mut self, entry: &[u8], value: Vec<u8>) -> Vec<u8> {
+        self.revised(entry, value, 0)
+    }
+
+    /// As `revise`, holding the counter `count` instead.
+    pub fn recount(&mut self, entry: &[u8], count: u64) -> Vec<u8> {
+        self.revised(entry, counter::encode_value(count), COUNTER)
+    }
+
+    fn revised(&mut self, entry: &[u8], value: Vec<u8>, flags: u8) -> Vec<u8> {
+        let version = self.next_version();
+        let mut revised = entry[..HEADER_LENGTH].to_vec();
+        BigEndian::write_u64(&mut revised[8..FLAGS], version);
+        revised[FLAGS] = flags;
+        revised.extend(value);
+        revised
+    }
+
+    /// Counts versions up, wrapping round from `u64::MAX` to 1 since 0 means
+    /// no version on the wire. Only a loaded entry can bring the count near
+    /// the top, as it starts from the clock.
+    fn next_version(&mut self) -> u64 {
+        self.version = self.version.checked_add(1).unwrap_or(1);
+        self.version
+    }
+
+    /// Looks up `key` as `get` does, giving only a hit's version.
+    pub fn version(&mut self, key: &Vec<u8>) -> Lookup<u64> {
+        let found = self.cache.get(key).map(|entry| (is_live(entry), version(entry)));
+
+        match found {
+            Some((true, version)) => Lookup::Hit(version),
+            Some((false, _)) => {
+                self.cache.remove(key);
+                Lookup::Expired
+            },
+            None => Lookup::Miss
+        }
+    }
+
+    pub fn stats(&self) -> Stats {
+        Stats {
+            entries: self.cache.len(),
+            bytes: self.cache.bytes(),
+            evictions: self.cache.evictions(),
+            max_entries: self.cache.limits().max_entries,
+            max_bytes: self.cache.limits().max_bytes
+        }
+    }
+
+    /// Every live entry, header included, in the order the policy
+    /// would evict them.
+    pub fn dump(&self) -> Vec<(Vec<u8>, Vec<u8>)> {
+        self.cache.eviction_order().into_iter()
+            .filter(|&(_, entry)| is_live(entry))
+            .map(|(key, entry)| (key.clone(), entry.clone()))
+            .collect()
+    }
+
+    /// Stores an entry taken from `dump`, unless it has expired since.
+    pub fn load(&mut self, key: Vec<u8>, entry: Vec<u8>) -> bool {
+        entry.len() >= HEADER_LENGTH && is_live(&entry) && self.put_entry(key, entry)
+    }
+
+    pub fn clear(&mut self) {
+        let keys: Vec<Vec<u8>> = self.cache.eviction_order().into_iter().map(|(key, _)| key.clone()).collect();
+        for key in keys {
+            self.cache.remove(&key);
+        }
+    }
+
+    pub fn remove(&mut self, key: &Vec<u8>) -> Lookup {
+        match self.cache.remove(key) {
+            Some(ref entry) if is_live(entry) => Lookup::Hit(entry[HEADER_LENGTH..].to_vec()),
+            Some(_) => Lookup::Expired,
+            None => Lookup::Miss
+        }
+    }
+}
+
+/// A value behind the header recording when it expires, `ttl` from now, and
+/// its version. A `ttl` too long for `expires_at` never expires.
+pub fn entry(value: Vec<u8>, ttl: Option<Duration>, version: u64) -> Vec<u8> {
+    let expires_at = ttl.map(|ttl| expires_at(ttl).unwrap_or(u64::MAX)).unwrap_or(0);
+    let mut entry = vec![0; HEADER_LENGTH];
+    BigEndian::write_u64(&mut entry[..8], expires_at);
+    BigEndian::write_u64(&mut entry[8..FLAGS], version);
+    entry.extend(value);
+    entry
+}
+
+fn is_live(entry: &[u8]) -> bool {
+    let expires_at = BigEndian::read_u64(&entry[..8]);
+    expires_at == 0 || now() < expires_at
+}
+
+/// The value of an entry built by `entry`, without its header.
+pub fn value(entry: &[u8]) -> &[u8] {
+    &entry[HEADER_LENGTH..]
+}
+
+/// The version recorded in the header of an entry built by `entry`.
+pub fn version(entry: &[u8]) -> u64 {
+    BigEndian::read_u64(&entry[8..FLAGS])
+}
+
+/// The count held by an entry built by `Store::counter` or `Store::recount`,
+/// or `None` if the entry holds any other value.
+pub fn count(entry: &[u8]) -> Option<u64> {
+    if entry[FLAGS] & COUNTER != 0 {
+        counter::decode_value(value(entry))
+    } else {
+        None
+    }
+}
+
+/// When an entry stored now with `ttl` expires, in milliseconds since the
+/// epoch, or `None` if that is too far off to record.
+pub fn expires_at(ttl: Duration) -> Option<u64> {
+    as_millis(ttl).and_then(|ttl| now().checked_add(ttl))
+}
+
+/// Milliseconds since the epoch.
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).ok().and_then(as_millis).unwrap_or(0)
+}
+
+fn as_millis(duration: Duration) -> Option<u64> {
+    duration.as_secs().checked_mul(1000)
+            .and_then(|millis| millis.checked_add((duration.subsec_nanos() / 1_000_000) as u64))
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+    use std::time::Duration;
+    use cache_codec::counter;
+    use storage::{ Limits, Policy };
+    use super::{ Store, Lookup, Stats };
+
+    #[test]
+    fn test_gets_entry_without_ttl() {
+        let mut store = Store::new(8);
+        store.put(b"key".to_vec(), vec![1, 2, 3], None);
+
+        assert_eq!(store.get(&b"key".to_vec()), Lookup::Hit(vec![1, 2, 3]));
+    }
+
+    #[test]
+    fn test_gets_entry_before_expiry() {
+        let mut store = Store::new(8);
+        store.put(b"key".to_vec(), vec![1, 2, 3], Some(Duration::from_secs(60)));
+
+        assert_eq!(store.get(&b"key".to_vec()), Lookup::Hit(vec![1, 2, 3]));
+    }
+
+    #[test]
+    fn test_expired_entry_is_not_returned() {
+        let mut store = Store::new(8);
+        store.put(b"key".to_vec(), vec![1, 2, 3], Some(Duration::from_millis(10)));
+        thread::sleep(Duration::from_millis(20));
+
+        assert_eq!(store.get(&b"key".to_vec()), Lookup::Expired);
+        assert_eq!(store.get(&b"key".to_vec()), Lookup::Miss);
+    }
+
+    #[test]
+    fn test_huge_ttl_never_expires() {
+        let mut store = Store::new(8);
+        store.put(b"key".to_vec(), vec![1], Some(Duration::from_secs(u64::MAX)));
+
+        assert_eq!(super::expires_at(Duration::from_secs(u64::MAX)), None);
+        assert_eq!(store.get(&b"key".to_vec()), Lookup::Hit(vec![1]));
+    }
+
+    #[test]
+    fn test_put_without_ttl_clears_expiry() {
+        let mut store = Store::new(8);
+        store.put(b"key".to_vec(), vec![1], Some(Duration::from_millis(10)));
+        store.put(b"key".to_vec(), vec![2], None);
+        thread::sleep(Duration::from_millis(20));
+
+        assert_eq!(store.get(&b"key".to_vec()), Lookup::Hit(vec![2]));
+    }
+
+    #[test]
+    fn test_remove_reports_expired_entry() {
+        let mut store = Store::new(8);
+        store.put(b"key".to_vec(), vec![1], Some(Duration::from_millis(10)));
+        thread::sleep(Duration::from_millis(20));
+
+        assert_eq!(store.remove(&b"key".to_vec()), Lookup::Expired);
+        assert_eq!(store.remove(&b"key".to_vec()), Lookup::Miss);
+    }
+
+    #[test]
+    fn test_byte_limit_evicts_older_entries() {
+        let mut store = Store::with_policy(Policy::Lru, Limits::new(None, None));
+        store.put(b"old".to_vec(), vec![0; 32], None);
+        let entry_size = store.stats().bytes;
+
+        let mut store = Store::with_policy(Policy::Lru, Limits::new(None, Some(entry_size * 3 / 2)));
+        store.put(b"old".to_vec(), vec![0; 32], None);
+        store.put(b"new".to_vec(), vec![0; 32], None);
+
+        assert_eq!(store.get(&b"old".to_vec()), Lookup::Miss);
+        assert_eq!(store.get(&b"new".to_vec()), Lookup::Hit(vec![0; 32]));
+        assert_eq!(store.stats().evictions, 1);
+    }
+
+    #[test]
+    fn test_stats_report_usage() {
+        let mut store = Store::with_policy(Policy::Arc, Limits::new(Some(8), None));
+        store.put(b"key".to_vec(), vec![0; 100], None);
+        let stats = store.stats();
+
+        assert_eq!(stats.entries, 1);
+        assert!(stats.bytes > 100);
+        assert_eq!(stats.max_entries, Some(8));
+        assert_eq!(stats.max_bytes, None);
+
+        store.remove(&b"key".to_vec());
+        assert_eq!(store.stats(), Stats { bytes: 0, entries: 0, ..stats });
+    }
+
+    #[test]
+    fn test_dump_and_load_keep_order_and_expiry() {
+        let mut store = Store::new(3);
+        store.put(b"old".to_vec(), vec![1], None);
+        store.put(b"new".to_vec(), vec![2], Some(Duration::from_secs(60)));
+        store.put(b"gone".to_vec(), vec![3], Some(Duration::from_millis(10)));
+        store.get(&b"old".to_vec());
+        thread::sleep(Duration::from_millis(20));
+        let dumped = store.dump();
+
+        let mut restored = Store::new(2);
+        for (key, entry) in dumped {
+            restored.load(key, entry);
+        }
+        restored.put(b"newest".to_vec(), vec![4], None);
+
+        assert_eq!(restored.get(&b"new".to_vec()), Lookup::Miss);
+        assert_eq!(restored.get(&b"old".to_vec()), Lookup::Hit(vec![1]));
+        assert_eq!(restored.get(&b"gone".to_vec()), Lookup::Miss);
+    }
+
+    #[test]
+    fn test_each_put_gets_a_new_version() {
+        let mut store = Store::new(8);
+        store.put(b"key".to_vec(), vec![1], None);
+        let first = match store.version(&b"key".to_vec()) {
+            Lookup::Hit(version) => version,
+            other => panic!("unexpected lookup {:?}", other)
+        };
+        store.put(b"key".to_vec(), vec![2], None);
+        let second = match store.version(&b"key".to_vec()) {
+            Lookup::Hit(version) => version,
+            other => panic!("unexpected lookup {:?}", other)
+        };
+
+        assert!(second > first);
+        assert_eq!(store.get_versioned(&b"key".to_vec()), Lookup::Hit((vec![2], second)));
+        assert_eq!(store.version(&b"missing".to_vec()), Lookup::Miss);
+    }
+
+    #[test]
+    fn test_revised_entry_keeps_expiry() {
+        let mut store = Store::new(8);
+        store.put(b"key".to_vec(), vec![1], Some(Duration::from_millis(10)));
+        let old = match store.get_entry(&b"key".to_vec()) {
+            Lookup::Hit(entry) => entry,
+            other => panic!("unexpected lookup {:?}", other)
+        };
+        let revised = store.revise(&old, vec![2]);
+        store.put_entry(b"key".to_vec(), revised.clone());
+
+        assert_eq!(super::value(&revised), &[2]);
+        assert!(super::version(&revised) > super::version(&old));
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(store.get(&b"key".to_vec()), Lookup::Expired);
+    }
+
+    #[test]
+    fn test_only_counter_entries_hold_counts() {
+        let mut store = Store::new(8);
+        let counter = store.counter(7, None);
+        assert_eq!(super::count(&counter), Some(7));
+        assert_eq!(super::value(&counter), &[0, 0, 0, 0, 0, 0, 0, 7]);
+
+        let recounted = store.recount(&counter, 8);
+        assert_eq!(super::count(&recounted), Some(8));
+        assert_eq!(super::count(&store.revise(&recounted, b"abcdefgh".to_vec())), None);
+        assert_eq!(super::count(&store.entry(counter::encode_value(7), None)), None);
+    }
+
+    #[test]
+    fn test_loaded_versions_are_not_reused() {
+        let mut store = Store::new(8);
+        let entry = super::entry(vec![1], None, u64::MAX - 1);
+        store.load(b"loaded".to_vec(), entry);
+        store.put(b"key".to_vec(), vec![2], None);
+
+        assert_eq!(store.version(&b"key".to_vec()), Lookup::Hit(u64::MAX));
+    }
+
+    #[test]
+    fn test_versions_wrap_round_to_one() {
+        let mut store = Store::new(8);
+        store.load(b"loaded".to_vec(), super::entry(vec![1], None, u64::MAX));
+        store.put(b"key".to_vec(), vec![2], None);
+        store.put(b"other".to_vec(), vec![3], None);
+
+        assert_eq!(store.version(&b"key".to_vec()), Lookup::Hit(1));
+        assert_eq!(store.version(&b"other".to_vec()), Lookup::Hit(2));
+    }
+}
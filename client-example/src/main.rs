@@ -4,6 +4,7 @@ extern crate cache_client;
 use clap::{ Arg, App };
 use std::io;
 use std::io::{ BufReader, BufRead };
+use std::time::Duration;
 use cache_client::{ Cacheable, CacheClient };
 
 struct CacheString {
@@ -12,8 +13,8 @@ struct CacheString {
 }
 
 impl Cacheable for CacheString {
-    fn key(&self) -> String {
-        self.key.clone()
+    fn key(&self) -> Vec<u8> {
+        self.key.as_bytes().to_vec()
     }
 
     fn value(&self) -> Vec<u8> {
@@ -23,7 +24,7 @@ impl Cacheable for CacheString {
     fn value_from_bytes(&self, val: Vec<u8>) -> Self {
         CacheString {
             key: self.key.clone(),
-            value: String::from_utf8(val).unwrap()
+            value: String::from_utf8_lossy(&val).into_owned()
         }
     }
 }
@@ -40,7 +41,48 @@ fn process_line(line: String, client: &CacheClient) {
         };
         let result = client.get(cache_string);
         match result {
-            Ok(returned) => println!("Key: {}, Value: {}", returned.key, returned.value),
+            Ok(Some(returned)) => println!("Key: {}, Value: {}", returned.key, returned.value),
+            Ok(None) => println!("Key: {} not found", words[1]),
+            Err(e) => println!("Error: {}", e)
+        }
+    } else if words[0] == "gets" {
+        let cache_string = CacheString {
+            key: words[1].to_string(),
+            value: "".to_string()
+        };
+        match client.get_versioned(cache_string) {
+            Ok(Some((returned, version))) => println!("Key: {}, Value: {}, Version: {}", returned.key, returned.value, version),
+            Ok(None) => println!("Key: {} not found", words[1]),
+            Err(e) => println!("Error: {}", e)
+        }
+    } else if words[0] == "cas" {
+        let cache_string = CacheString {
+            key: words[1].to_string(),
+            value: words[2].to_string()
+        };
+        match words.get(3).and_then(|version| version.parse().ok()) {
+            Some(version) => match client.cas(cache_string, version) {
+                Ok(true) => println!("Key: {} cached", words[1]),
+                Ok(false) => println!("Key: {} has changed", words[1]),
+                Err(e) => println!("Error: {}", e)
+            },
+            None => println!("invalid version")
+        }
+    } else if words[0] == "incr" || words[0] == "decr" {
+        let cache_string = CacheString {
+            key: words[1].to_string(),
+            value: "".to_string()
+        };
+        let delta = words.get(2).and_then(|delta| delta.parse().ok()).unwrap_or(1);
+        let initial = words.get(3).and_then(|initial| initial.parse().ok());
+        let result = match (words[0] == "incr", initial) {
+            (true, Some(initial)) => client.incr_or_init(cache_string, delta, initial, None),
+            (true, None) => client.incr(cache_string, delta),
+            (false, Some(initial)) => client.decr_or_init(cache_string, delta, initial, None),
+            (false, None) => client.decr(cache_string, delta)
+        };
+        match result {
+            Ok(count) => println!("Key: {}, Count: {}", words[1], count),
             Err(e) => println!("Error: {}", e)
         }
     } else if words[0] == "put" {
@@ -48,12 +90,86 @@ fn process_line(line: String, client: &CacheClient) {
             key: words[1].to_string(),
             value: words[2].to_string()
         };
-        match client.put(cache_string) {
+        let result = match words.get(3).and_then(|ttl| ttl.parse().ok()) {
+            Some(ttl) => client.put_with_ttl(cache_string, Duration::from_secs(ttl)),
+            None => client.put(cache_string)
+        };
+        match result {
             Ok(returned) => println!("Key: {} cached", returned.key),
             Err(e) => println!("Error: {}", e)
         }
+    } else if words[0] == "add" || words[0] == "replace" {
+        let cache_string = CacheString {
+            key: words[1].to_string(),
+            value: words[2].to_string()
+        };
+        let result = if words[0] == "add" { client.add(cache_string) } else { client.replace(cache_string) };
+        match result {
+            Ok(true) => println!("Key: {} cached", words[1]),
+            Ok(false) if words[0] == "add" => println!("Key: {} already cached", words[1]),
+            Ok(false) => println!("Key: {} not found", words[1]),
+            Err(e) => println!("Error: {}", e)
+        }
+    } else if words[0] == "append" || words[0] == "prepend" {
+        let cache_string = CacheString {
+            key: words[1].to_string(),
+            value: words[2].to_string()
+        };
+        let result = if words[0] == "append" { client.append(cache_string) } else { client.prepend(cache_string) };
+        match result {
+            Ok(true) => println!("Key: {} cached", words[1]),
+            Ok(false) => println!("Key: {} not found", words[1]),
+            Err(e) => println!("Error: {}", e)
+        }
+    } else if words[0] == "del" {
+        let cache_string = CacheString {
+            key: words[1].to_string(),
+            value: "".to_string()
+        };
+        match client.delete(cache_string) {
+            Ok(true) => println!("Key: {} deleted", words[1]),
+            Ok(false) => println!("Key: {} not found", words[1]),
+            Err(e) => println!("Error: {}", e)
+        }
+    } else if words[0] == "mget" {
+        let cache_strings = words[1..].iter().map(|key| CacheString {
+            key: key.to_string(),
+            value: "".to_string()
+        }).collect();
+        for (key, result) in words[1..].iter().zip(client.get_many(cache_strings)) {
+            match result {
+                Ok(Some(returned)) => println!("Key: {}, Value: {}", returned.key, returned.value),
+                Ok(None) => println!("Key: {} not found", key),
+                Err(e) => println!("Key: {} error: {}", key, e)
+            }
+        }
+    } else if words[0] == "mset" {
+        let cache_strings = words[1..].chunks(2).filter(|pair| pair.len() == 2).map(|pair| CacheString {
+            key: pair[0].to_string(),
+            value: pair[1].to_string()
+        }).collect();
+        for result in client.put_many(cache_strings) {
+            match result {
+                Ok(returned) => println!("Key: {} cached", returned.key),
+                Err(e) => println!("Error: {}", e)
+            }
+        }
+    } else if words[0] == "stats" {
+        match client.stats() {
+            Ok(stats) => {
+                for (name, value) in stats {
+                    println!("{}: {}", name, value);
+                }
+            },
+            Err(e) => println!("Error: {}", e)
+        }
     } else {
-        println!("invalid command: `put <key> <value>` or `get <key>`");
+        println!("invalid command: `put <key> <value> [ttl]`, `get <key>`, `del <key>`, \
+                  `add <key> <value>`, `replace <key> <value>`, \
+                  `append <key> <value>`, `prepend <key> <value>`, \
+                  `gets <key>`, `cas <key> <value> <version>`, \
+                  `incr <key> [delta] [initial]`, `decr <key> [delta] [initial]`, \
+                  `mget <key>...`, `mset <key> <value>...` or `stats`");
     }
 
 }